@@ -0,0 +1,80 @@
+//! Counts heap allocations per fill when matching against a deep price
+//! level. Run with `cargo bench --bench deep_level`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use basic_order_book::{Order, OrderBook, Quantity, Side};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const DEPTH: u32 = 10_000;
+
+fn sell(quantity: u32) -> Order {
+    Order::new("MAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(quantity), Side::Sell)
+}
+
+fn buy(quantity: u32) -> Order {
+    Order::new("TAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(quantity), Side::Buy)
+}
+
+/// Returns the allocations and microseconds spent submitting `takers`.
+fn measure(book: &mut OrderBook, takers: Vec<Order>) -> (usize, u128) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for order in takers {
+        book.submit(order);
+    }
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, start.elapsed().as_micros())
+}
+
+fn main() {
+    // One buy sweeping a level of DEPTH single-unit sells
+    let mut book = OrderBook::new("BENCH".to_string());
+    for _ in 0..DEPTH {
+        book.submit(sell(1));
+    }
+    let (sweep_allocations, sweep_micros) = measure(&mut book, vec![buy(DEPTH)]);
+
+    // DEPTH single-unit buys each taking part of one large resting sell
+    let mut book = OrderBook::new("BENCH".to_string());
+    book.submit(sell(DEPTH + 1));
+    let takers = (0..DEPTH).map(|_| buy(1)).collect();
+    let (partial_allocations, partial_micros) = measure(&mut book, takers);
+
+    println!("Matching against a level {} orders or units deep", DEPTH);
+    println!(
+        "  one buy sweeping the level:       {:>8} allocations ({:.1} per fill), {} us",
+        sweep_allocations,
+        sweep_allocations as f64 / DEPTH as f64,
+        sweep_micros
+    );
+    println!(
+        "  small buys filling one large sell: {:>7} allocations ({:.1} per fill), {} us",
+        partial_allocations,
+        partial_allocations as f64 / DEPTH as f64,
+        partial_micros
+    );
+}
@@ -0,0 +1,88 @@
+//! Counts heap allocations and time for a run of crossing orders, with and
+//! without trade pooling. Run with `cargo bench --bench trade_pool`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use basic_order_book::{Order, OrderBook, Quantity, Side};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ROUNDS: usize = 20_000;
+
+/// Rests a sell and crosses it with a buy each round, handing every trade
+/// back to the book once done with it. Orders are built up front so only
+/// the book's own allocations are counted.
+fn run(mut book: OrderBook) -> (usize, u128) {
+    let orders: Vec<(Order, Order)> = (0..ROUNDS)
+        .map(|_| {
+            (
+                Order::new("MAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(10), Side::Sell),
+                Order::new("TAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(10), Side::Buy),
+            )
+        })
+        .collect();
+
+    // Warm up so the pool and the book's collections reach their working size
+    for _ in 0..100 {
+        book.submit(Order::new("MAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(10), Side::Sell));
+        let outcome = book.submit(Order::new("TAKER".to_string(), "BENCH".to_string(), 100, Quantity::from(10), Side::Buy));
+        book.recycle_trades(outcome.trades);
+        let history = book.drain_trades();
+        book.recycle_trades(history);
+    }
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for (sell, buy) in orders {
+        book.submit(sell);
+        let outcome = book.submit(buy);
+        book.recycle_trades(outcome.trades);
+        let history = book.drain_trades();
+        book.recycle_trades(history);
+    }
+    let elapsed = start.elapsed().as_micros();
+
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, elapsed)
+}
+
+fn main() {
+    let (plain_allocations, plain_micros) = run(OrderBook::new("BENCH".to_string()));
+    let (pooled_allocations, pooled_micros) = run(OrderBook::with_trade_pool("BENCH".to_string(), 16));
+
+    println!("{} rounds of one resting sell and one crossing buy", ROUNDS);
+    println!(
+        "  OrderBook::new:             {:>8} allocations ({:.1} per trade), {} us",
+        plain_allocations,
+        plain_allocations as f64 / ROUNDS as f64,
+        plain_micros
+    );
+    println!(
+        "  OrderBook::with_trade_pool: {:>8} allocations ({:.1} per trade), {} us",
+        pooled_allocations,
+        pooled_allocations as f64 / ROUNDS as f64,
+        pooled_micros
+    );
+}
@@ -0,0 +1,5453 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet, VecDeque};
+use std::sync::mpsc::Sender;
+use std::sync::RwLock;
+use std::fmt::{self, Write};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Bound, Sub, SubAssign};
+use std::str::FromStr;
+use chrono::{DateTime, Utc};
+use rand::{Rng, SeedableRng};
+use rand::distributions::Alphanumeric;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    /// The side an order on this side trades against.
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Buy => write!(f, "BUY"),
+            Side::Sell => write!(f, "SELL"),
+        }
+    }
+}
+
+/// A quantity of an instrument, exact to `Quantity::DECIMALS` decimal places
+/// so that fractional units can trade. Whole numbers convert with
+/// `From<u32>`, or `TryFrom<u64>` for larger ones, and decimals parse from
+/// strings, e.g. `"0.25".parse::<Quantity>()`.
+///
+/// Arithmetic is exact and a quantity is never negative: a subtraction that
+/// would go below zero, or any overflow, panics instead of wrapping.
+///
+/// With serde a quantity is the decimal string it displays as, so that no
+/// precision is lost to floating point.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct Quantity(u64);
+
+impl Quantity {
+    /// How many decimal places a quantity is exact to.
+    pub const DECIMALS: u32 = 8;
+    pub const ZERO: Quantity = Quantity(0);
+    pub const MAX: Quantity = Quantity(u64::MAX);
+    /// The raw count of one whole unit.
+    const UNIT: u64 = 10u64.pow(Quantity::DECIMALS);
+
+    /// A quantity from its raw count of the smallest increment, 10^-8.
+    pub const fn from_raw(raw: u64) -> Quantity {
+        Quantity(raw)
+    }
+
+    /// The quantity as a count of the smallest increment, 10^-8.
+    pub const fn raw(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Quantity) -> Option<Quantity> {
+        self.0.checked_add(other.0).map(Quantity)
+    }
+
+    pub fn checked_sub(self, other: Quantity) -> Option<Quantity> {
+        self.0.checked_sub(other.0).map(Quantity)
+    }
+
+    pub fn saturating_sub(self, other: Quantity) -> Quantity {
+        Quantity(self.0.saturating_sub(other.0))
+    }
+
+    /// The quantity as a floating point number of units, for statistics.
+    pub fn to_f64(self) -> f64 {
+        self.0 as f64 / Quantity::UNIT as f64
+    }
+}
+
+/// A value that is not a valid `Quantity`: negative, more precise than
+/// `Quantity::DECIMALS` places, or above `Quantity::MAX`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuantityError;
+
+impl fmt::Display for QuantityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid quantity")
+    }
+}
+
+impl std::error::Error for QuantityError {}
+
+impl From<u32> for Quantity {
+    /// `whole` units. Every `u32` fits, so this cannot fail.
+    fn from(whole: u32) -> Self {
+        Quantity(whole as u64 * Quantity::UNIT)
+    }
+}
+
+impl TryFrom<u64> for Quantity {
+    type Error = QuantityError;
+
+    /// `whole` units, failing above `Quantity::MAX`, about 1.8 * 10^11 units.
+    fn try_from(whole: u64) -> Result<Self, Self::Error> {
+        whole.checked_mul(Quantity::UNIT).map(Quantity).ok_or(QuantityError)
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = QuantityError;
+
+    /// Parses a non-negative decimal such as `"12"`, `"0.5"` or `".5"`, with
+    /// at most `Quantity::DECIMALS` decimal places.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+        let digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if (whole.is_empty() && fraction.is_empty())
+            || fraction.len() > Quantity::DECIMALS as usize
+            || !digits(whole)
+            || !digits(fraction)
+        {
+            return Err(QuantityError);
+        }
+
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| QuantityError)? };
+        let fraction: u64 = format!("{:0<width$}", fraction, width = Quantity::DECIMALS as usize)
+            .parse()
+            .map_err(|_| QuantityError)?;
+
+        whole
+            .checked_mul(Quantity::UNIT)
+            .and_then(|raw| raw.checked_add(fraction))
+            .map(Quantity)
+            .ok_or(QuantityError)
+    }
+}
+
+impl TryFrom<String> for Quantity {
+    type Error = QuantityError;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Quantity> for String {
+    fn from(quantity: Quantity) -> Self {
+        quantity.to_string()
+    }
+}
+
+impl fmt::Display for Quantity {
+    /// Whole quantities print without a decimal point, others with only the
+    /// decimal places they need: `20`, `2.5`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (whole, fraction) = (self.0 / Quantity::UNIT, self.0 % Quantity::UNIT);
+        if fraction == 0 {
+            return f.pad(&whole.to_string());
+        }
+
+        let fraction = format!("{:0width$}", fraction, width = Quantity::DECIMALS as usize);
+        f.pad(&format!("{}.{}", whole, fraction.trim_end_matches('0')))
+    }
+}
+
+impl fmt::Debug for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Quantity({})", self)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+
+    fn add(self, other: Quantity) -> Quantity {
+        self.checked_add(other).expect("quantity overflow")
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, other: Quantity) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+
+    fn sub(self, other: Quantity) -> Quantity {
+        self.checked_sub(other)
+            .unwrap_or_else(|| panic!("quantity {} minus {} would be negative", self, other))
+    }
+}
+
+impl SubAssign for Quantity {
+    fn sub_assign(&mut self, other: Quantity) {
+        *self = *self - other;
+    }
+}
+impl Sum for Quantity {
+    fn sum<I: Iterator<Item = Quantity>>(iter: I) -> Quantity {
+        iter.fold(Quantity::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Quantity> for Quantity {
+    fn sum<I: Iterator<Item = &'a Quantity>>(iter: I) -> Quantity {
+        iter.copied().sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderType {
+    /// Trades at `price` or better; any remainder rests in the book.
+    Limit,
+    /// Trades at any price; any remainder is cancelled rather than rested.
+    Market,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    id: String,
+    trader_id: String,
+    symbol: String,
+    price: u64,
+    quantity: Quantity,
+    side: Side,
+    timestamp: DateTime<Utc>,
+    order_type: OrderType,
+    max_slippage_bps: Option<u32>,
+    min_rest_qty: Option<Quantity>,
+    min_exec_qty: Option<Quantity>,
+    expires_at: Option<DateTime<Utc>>,
+    stop_price: Option<u64>,
+    seq: u64,
+    queue_jump: u64,
+    peg: Option<PegType>,
+    peg_offset: i64,
+    hidden: bool,
+    all_or_none: bool,
+    client_order_id: Option<String>,
+    reduce_only: bool,
+}
+
+impl Order {
+    /// Creates a limit order. The symbol is trimmed and uppercased.
+    pub fn new(trader_id: String, symbol: String, price: u64, quantity: Quantity, side: Side) -> Self {
+        Order {
+            id: Uuid::new_v4().to_string(),
+            trader_id,
+            symbol: normalize_symbol(&symbol),
+            price,
+            quantity,
+            side,
+            timestamp: Utc::now(),
+            order_type: OrderType::Limit,
+            max_slippage_bps: None,
+            min_rest_qty: None,
+            min_exec_qty: None,
+            expires_at: None,
+            stop_price: None,
+            seq: 0,
+            queue_jump: 0,
+            peg: None,
+            peg_offset: 0,
+            hidden: false,
+            all_or_none: false,
+            client_order_id: None,
+            reduce_only: false,
+        }
+    }
+
+    /// Creates a limit order like `new`, but fails if the trader id or the
+    /// symbol is empty or only whitespace.
+    pub fn try_new(trader_id: String, symbol: String, price: u64, quantity: Quantity, side: Side) -> Result<Self, OrderError> {
+        if trader_id.trim().is_empty() {
+            return Err(OrderError::EmptyTraderId);
+        }
+        if symbol.trim().is_empty() {
+            return Err(OrderError::EmptySymbol);
+        }
+        Ok(Order::new(trader_id, symbol, price, quantity, side))
+    }
+
+    /// Creates a limit order with the given id instead of a random one.
+    pub fn new_with_id(id: String, trader_id: String, symbol: String, price: u64, quantity: Quantity, side: Side) -> Self {
+        Order {
+            id,
+            ..Order::new(trader_id, symbol, price, quantity, side)
+        }
+    }
+
+    /// Creates a market order. Its price is unused and reported as zero.
+    pub fn market(trader_id: String, symbol: String, quantity: Quantity, side: Side) -> Self {
+        Order {
+            order_type: OrderType::Market,
+            ..Order::new(trader_id, symbol, 0, quantity, side)
+        }
+    }
+
+    /// Creates a stop order: it waits off the book until the market reaches
+    /// `stop_price` (at or above for a buy, at or below for a sell) and then
+    /// enters as a market order.
+    pub fn stop(trader_id: String, symbol: String, stop_price: u64, quantity: Quantity, side: Side) -> Self {
+        Order {
+            stop_price: Some(stop_price),
+            ..Order::market(trader_id, symbol, quantity, side)
+        }
+    }
+
+    /// Creates a stop-limit order: like `stop`, but once triggered it enters
+    /// as a limit order at `limit_price`, resting whatever does not fill.
+    pub fn stop_limit(
+        trader_id: String,
+        symbol: String,
+        stop_price: u64,
+        limit_price: u64,
+        quantity: Quantity,
+        side: Side,
+    ) -> Self {
+        Order {
+            stop_price: Some(stop_price),
+            ..Order::new(trader_id, symbol, limit_price, quantity, side)
+        }
+    }
+
+    /// Pegs the order to `peg` plus `offset`. While resting it is repriced
+    /// whenever the reference moves, losing its time priority each time. The
+    /// order's own price is only used while the reference is unavailable.
+    pub fn with_peg(mut self, peg: PegType, offset: i64) -> Self {
+        self.peg = Some(peg);
+        self.peg_offset = offset;
+        self
+    }
+
+    /// Hides the order completely: it is left out of every depth and display
+    /// view but still matches, after the displayed orders at its price.
+    pub fn with_hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    /// Makes the order all-or-none while it rests: incoming orders skip it
+    /// unless they can take its whole quantity. As an incoming order it
+    /// matches as usual, and any remainder rests all-or-none. Auctions run
+    /// by `uncross` do not honor the flag.
+    pub fn with_all_or_none(mut self) -> Self {
+        self.all_or_none = true;
+        self
+    }
+
+    /// Lets the order only shrink its trader's net position in the book,
+    /// not open or flip it. When it comes to match it is cut down to the
+    /// position it can close, or rejected if there is none. A remainder that
+    /// rests is not resized again if the position later changes.
+    pub fn with_reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+
+    /// Tags the order with the trader's own id for it. A book refuses a
+    /// second live order with the same tag from the same trader.
+    pub fn with_client_order_id(mut self, client_order_id: &str) -> Self {
+        self.client_order_id = Some(client_order_id.to_string());
+        self
+    }
+
+    /// Protects a market order from walking the book: matching stops, and the
+    /// remainder is cancelled, at the first level priced more than `bps` basis
+    /// points worse than the order's first fill.
+    pub fn with_max_slippage_bps(mut self, bps: u32) -> Self {
+        self.max_slippage_bps = Some(bps);
+        self
+    }
+
+    /// Cancels, rather than rests, any remainder smaller than `quantity` left
+    /// after the order has partly matched. An order that has not traded at
+    /// all rests as usual.
+    pub fn with_min_rest_qty(mut self, quantity: Quantity) -> Self {
+        self.min_rest_qty = Some(quantity);
+        self
+    }
+
+    /// Requires the order to trade at least `quantity` (or all of it, if
+    /// smaller) on entry. If the book cannot fill that much the order is
+    /// rejected without trading; otherwise it matches and rests as usual.
+    pub fn with_min_exec_qty(mut self, quantity: Quantity) -> Self {
+        self.min_exec_qty = Some(quantity);
+        self
+    }
+
+    /// Makes the order good-till-date: once resting, it is removed by
+    /// `OrderBook::expire_orders` at or after `expires_at`.
+    pub fn with_expiry(mut self, expires_at: DateTime<Utc>) -> Self {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn trader_id(&self) -> &str {
+        &self.trader_id
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn price(&self) -> u64 {
+        self.price
+    }
+
+    pub fn quantity(&self) -> Quantity {
+        self.quantity
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    /// When the order was created, or, once a book has accepted it, the
+    /// book's clock time at acceptance.
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn order_type(&self) -> OrderType {
+        self.order_type
+    }
+
+    pub fn max_slippage_bps(&self) -> Option<u32> {
+        self.max_slippage_bps
+    }
+
+    pub fn min_rest_qty(&self) -> Option<Quantity> {
+        self.min_rest_qty
+    }
+
+    pub fn min_exec_qty(&self) -> Option<Quantity> {
+        self.min_exec_qty
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.expires_at
+    }
+
+    pub fn stop_price(&self) -> Option<u64> {
+        self.stop_price
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    pub fn is_all_or_none(&self) -> bool {
+        self.all_or_none
+    }
+
+    pub fn client_order_id(&self) -> Option<&str> {
+        self.client_order_id.as_deref()
+    }
+
+    pub fn is_reduce_only(&self) -> bool {
+        self.reduce_only
+    }
+
+    pub fn peg(&self) -> Option<(PegType, i64)> {
+        self.peg.map(|peg| (peg, self.peg_offset))
+    }
+
+    /// Sequence number assigned by the book when it accepted the order, or 0
+    /// if it has not been accepted. Orders at the same price from traders of
+    /// equal priority are matched in ascending `seq` order, except that
+    /// orders moved up by `OrderBook::improve_order` go first.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Whether this order, as the incoming side, can trade against a resting
+    /// order at `resting_price`.
+    fn can_trade_at(&self, resting_price: u64, cross_rule: CrossRule) -> bool {
+        match self.order_type {
+            OrderType::Market => true,
+            OrderType::Limit => crosses(self.side, self.price, resting_price, cross_rule),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Trade {
+    id: String,
+    buy_order_id: String,
+    sell_order_id: String,
+    buy_trader_id: String,
+    sell_trader_id: String,
+    symbol: String,
+    price: u64,
+    quantity: Quantity,
+    timestamp: DateTime<Utc>,
+    aggressor: Side,
+}
+
+impl Trade {
+    fn between(buy_order: &Order, sell_order: &Order, price: u64, quantity: Quantity, aggressor: Side, timestamp: DateTime<Utc>) -> Self {
+        Trade {
+            id: Uuid::new_v4().to_string(),
+            buy_order_id: buy_order.id.clone(),
+            sell_order_id: sell_order.id.clone(),
+            buy_trader_id: buy_order.trader_id.clone(),
+            sell_trader_id: sell_order.trader_id.clone(),
+            symbol: buy_order.symbol.clone(),
+            price,
+            quantity,
+            timestamp,
+            aggressor,
+        }
+    }
+
+    /// Overwrites this trade with a new execution, reusing its string
+    /// buffers instead of allocating new ones.
+    fn reuse(&mut self, buy_order: &Order, sell_order: &Order, price: u64, quantity: Quantity, aggressor: Side, timestamp: DateTime<Utc>) {
+        self.id.clear();
+        self.id.push_str(Uuid::new_v4().hyphenated().encode_lower(&mut Uuid::encode_buffer()));
+        self.buy_order_id.clone_from(&buy_order.id);
+        self.sell_order_id.clone_from(&sell_order.id);
+        self.buy_trader_id.clone_from(&buy_order.trader_id);
+        self.sell_trader_id.clone_from(&sell_order.trader_id);
+        self.symbol.clone_from(&buy_order.symbol);
+        self.price = price;
+        self.quantity = quantity;
+        self.timestamp = timestamp;
+        self.aggressor = aggressor;
+    }
+
+    /// Whether two trades record the same execution: the same orders,
+    /// traders, symbol, price, quantity and aggressor. Trade ids and
+    /// timestamps are not compared.
+    fn same_execution(&self, other: &Trade) -> bool {
+        self.buy_order_id == other.buy_order_id
+            && self.sell_order_id == other.sell_order_id
+            && self.buy_trader_id == other.buy_trader_id
+            && self.sell_trader_id == other.sell_trader_id
+            && self.symbol == other.symbol
+            && self.price == other.price
+            && self.quantity == other.quantity
+            && self.aggressor == other.aggressor
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn buy_order_id(&self) -> &str {
+        &self.buy_order_id
+    }
+
+    pub fn sell_order_id(&self) -> &str {
+        &self.sell_order_id
+    }
+
+    pub fn buy_trader_id(&self) -> &str {
+        &self.buy_trader_id
+    }
+
+    pub fn sell_trader_id(&self) -> &str {
+        &self.sell_trader_id
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn price(&self) -> u64 {
+        self.price
+    }
+
+    pub fn quantity(&self) -> Quantity {
+        self.quantity
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    /// The side of the order that took liquidity. For an auction trade this
+    /// is the side of the later of the two orders.
+    pub fn aggressor(&self) -> Side {
+        self.aggressor
+    }
+}
+
+impl Clone for Trade {
+    fn clone(&self) -> Self {
+        Trade {
+            id: self.id.clone(),
+            buy_order_id: self.buy_order_id.clone(),
+            sell_order_id: self.sell_order_id.clone(),
+            buy_trader_id: self.buy_trader_id.clone(),
+            sell_trader_id: self.sell_trader_id.clone(),
+            symbol: self.symbol.clone(),
+            price: self.price,
+            quantity: self.quantity,
+            timestamp: self.timestamp,
+            aggressor: self.aggressor,
+        }
+    }
+
+    // Reuses the existing buffers, which is what the trade pool relies on
+    fn clone_from(&mut self, source: &Self) {
+        self.id.clone_from(&source.id);
+        self.buy_order_id.clone_from(&source.buy_order_id);
+        self.sell_order_id.clone_from(&source.sell_order_id);
+        self.buy_trader_id.clone_from(&source.buy_trader_id);
+        self.sell_trader_id.clone_from(&source.sell_trader_id);
+        self.symbol.clone_from(&source.symbol);
+        self.price = source.price;
+        self.quantity = source.quantity;
+        self.timestamp = source.timestamp;
+        self.aggressor = source.aggressor;
+    }
+}
+
+/// What an amendment did to a resting order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmendmentKind {
+    /// The order was cancelled.
+    Cancel,
+    /// The order's quantity was reduced in place.
+    Reduce,
+    /// The order was cancelled and resubmitted with new terms.
+    Replace,
+    /// The order moved to a better price in place, keeping its id.
+    Improve,
+}
+
+/// One entry of the book's amendment log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Amendment {
+    order_id: String,
+    kind: AmendmentKind,
+    old_price: u64,
+    old_quantity: Quantity,
+    new_price: u64,
+    new_quantity: Quantity,
+    replacement_id: Option<String>,
+    timestamp: DateTime<Utc>,
+}
+
+impl Amendment {
+    pub fn order_id(&self) -> &str {
+        &self.order_id
+    }
+
+    pub fn kind(&self) -> AmendmentKind {
+        self.kind
+    }
+
+    pub fn old_price(&self) -> u64 {
+        self.old_price
+    }
+
+    pub fn old_quantity(&self) -> Quantity {
+        self.old_quantity
+    }
+
+    /// The price afterwards; unchanged for a cancel.
+    pub fn new_price(&self) -> u64 {
+        self.new_price
+    }
+
+    /// The quantity afterwards; zero for a cancel.
+    pub fn new_quantity(&self) -> Quantity {
+        self.new_quantity
+    }
+
+    /// The id of the order a `Replace` submitted in this one's place.
+    pub fn replacement_id(&self) -> Option<&str> {
+        self.replacement_id.as_deref()
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    /// The order's symbol is empty or only whitespace.
+    EmptySymbol,
+    /// The order's trader id is empty or only whitespace.
+    EmptyTraderId,
+    /// The order's symbol does not match the book's symbol.
+    SymbolMismatch,
+    /// The order has nothing to trade.
+    ZeroQuantity,
+    /// The side already holds the configured maximum number of resting orders.
+    BookFull,
+    /// No resting order has the given id.
+    OrderNotFound,
+    /// Another order in the book already has this order's id.
+    DuplicateOrderId,
+    /// No recorded trade has the given id.
+    TradeNotFound,
+    /// A market order found nothing to trade against.
+    NoLiquidity,
+    /// The book cannot fill as much of the order as it requires.
+    InsufficientLiquidity,
+    /// The spread is wider than the book allows market orders to cross.
+    SpreadTooWide,
+    /// A two-sided quote's bid is at or above its ask.
+    CrossedQuote,
+    /// The trader already has a live order with this client order id.
+    DuplicateClientOrderId,
+    /// A reduce-only order's trader has no position it would reduce.
+    NoPositionToReduce,
+    /// The operation only accepts plain limit orders.
+    UnsupportedOrderType,
+    /// The orders given would leave the best bid at or above the best ask,
+    /// or an improved price would trade against the other side.
+    CrossedBook,
+    /// The new price is not better than the order's current price.
+    NotAnImprovement,
+    /// The matching engine has no book for the order's symbol on the venue.
+    UnknownSymbol,
+    /// The trader has sent too many orders within the rate-limit window.
+    RateLimited,
+    /// The order would trade at a worse price than another venue displays.
+    TradeThrough,
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderError::EmptySymbol => write!(f, "order symbol is empty"),
+            OrderError::EmptyTraderId => write!(f, "order trader id is empty"),
+            OrderError::SymbolMismatch => write!(f, "order symbol does not match orderbook symbol"),
+            OrderError::ZeroQuantity => write!(f, "order quantity must be positive"),
+            OrderError::BookFull => write!(f, "order book side is full"),
+            OrderError::OrderNotFound => write!(f, "order not found"),
+            OrderError::DuplicateOrderId => write!(f, "order id already in use"),
+            OrderError::TradeNotFound => write!(f, "trade not found"),
+            OrderError::NoLiquidity => write!(f, "no liquidity to trade against"),
+            OrderError::InsufficientLiquidity => write!(f, "not enough liquidity to fill the order"),
+            OrderError::SpreadTooWide => write!(f, "spread too wide for a market order"),
+            OrderError::CrossedQuote => write!(f, "quote bid must be below its ask"),
+            OrderError::DuplicateClientOrderId => write!(f, "client order id already in use"),
+            OrderError::NoPositionToReduce => write!(f, "reduce-only order has no position to reduce"),
+            OrderError::UnsupportedOrderType => write!(f, "order type not supported here"),
+            OrderError::CrossedBook => write!(f, "resting orders would cross the book"),
+            OrderError::NotAnImprovement => write!(f, "new price does not improve the order"),
+            OrderError::UnknownSymbol => write!(f, "no order book for symbol"),
+            OrderError::RateLimited => write!(f, "order rate limit exceeded"),
+            OrderError::TradeThrough => write!(f, "a better price is available on another venue"),
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// The order was completely executed on arrival.
+    Filled,
+    /// Part of the order executed. `resting_quantity` is how much of the
+    /// remainder rests in the book; zero if it was cancelled instead, as
+    /// for a market order.
+    PartiallyFilled,
+    /// Nothing executed and the order is resting in the book untouched, with
+    /// its whole quantity as `resting_quantity`. Only self-trade prevention
+    /// or a reduce-only cut can make that less than the quantity submitted.
+    Resting,
+    /// The order is a stop waiting for its trigger price.
+    Pending,
+    /// Nothing executed and nothing is resting, e.g. because self-trade
+    /// prevention cancelled the order.
+    Cancelled,
+    /// The order, or its unfilled remainder, was not accepted by the book.
+    Rejected(OrderError),
+}
+
+/// An aggregated price level: `(price, total_quantity)`.
+pub type Level = (u64, Quantity);
+
+/// Aggregated view of both sides of a book at one point in time, as
+/// returned by `OrderBook::snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookSnapshot {
+    pub symbol: String,
+    /// Bid levels, best (highest) price first.
+    pub bids: Vec<Level>,
+    /// Ask levels, best (lowest) price first.
+    pub asks: Vec<Level>,
+}
+
+impl BookSnapshot {
+    /// Updates this snapshot in place with the changes in `delta`.
+    pub fn apply(&mut self, delta: &BookDelta) {
+        apply_level_deltas(&mut self.bids, &delta.bids, Side::Buy);
+        apply_level_deltas(&mut self.asks, &delta.asks, Side::Sell);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LevelChange {
+    /// A new price level with this quantity.
+    Added(Quantity),
+    /// An existing price level whose quantity is now this.
+    Updated(Quantity),
+    /// A price level that no longer exists.
+    Removed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelDelta {
+    pub price: u64,
+    pub change: LevelChange,
+}
+
+/// Per-level changes between two snapshots, as produced by `diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookDelta {
+    /// Changed bid levels, in ascending price order.
+    pub bids: Vec<LevelDelta>,
+    /// Changed ask levels, in ascending price order.
+    pub asks: Vec<LevelDelta>,
+}
+
+impl BookDelta {
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+}
+
+/// Returns how much would trade if two books for the same symbol were
+/// merged: `book_a`'s bids against `book_b`'s asks plus `book_b`'s bids
+/// against `book_a`'s asks, hidden orders included. Books for different
+/// symbols never cross.
+pub fn crossing_quantity(book_a: &OrderBook, book_b: &OrderBook) -> Quantity {
+    if book_a.symbol != book_b.symbol {
+        return Quantity::ZERO;
+    }
+
+    crossing_between(&book_a.buy_orders, &book_b.sell_orders) + crossing_between(&book_b.buy_orders, &book_a.sell_orders)
+}
+
+/// Quantity that matches when `bids` are matched best-first against `asks`
+/// for as long as the best remaining bid is at or above the best remaining
+/// ask.
+fn crossing_between(bids: &BTreeMap<u64, VecDeque<Order>>, asks: &BTreeMap<u64, VecDeque<Order>>) -> Quantity {
+    let total = |orders: &VecDeque<Order>| orders.iter().map(|order| order.quantity).sum::<Quantity>();
+    let mut bids = bids.iter().rev().map(|(&price, orders)| (price, total(orders)));
+    let mut asks = asks.iter().map(|(&price, orders)| (price, total(orders)));
+
+    let mut crossed = Quantity::ZERO;
+    let (mut bid, mut ask) = (bids.next(), asks.next());
+    while let (Some((bid_price, bid_qty)), Some((ask_price, ask_qty))) = (bid, ask) {
+        if bid_price < ask_price {
+            break;
+        }
+
+        let quantity = bid_qty.min(ask_qty);
+        crossed += quantity;
+        bid = if bid_qty > quantity { Some((bid_price, bid_qty - quantity)) } else { bids.next() };
+        ask = if ask_qty > quantity { Some((ask_price, ask_qty - quantity)) } else { asks.next() };
+    }
+
+    crossed
+}
+
+/// Computes the level changes that turn `prev` into `curr`.
+pub fn diff(prev: &BookSnapshot, curr: &BookSnapshot) -> BookDelta {
+    BookDelta {
+        bids: diff_levels(&prev.bids, &curr.bids),
+        asks: diff_levels(&prev.asks, &curr.asks),
+    }
+}
+
+fn diff_levels(prev: &[Level], curr: &[Level]) -> Vec<LevelDelta> {
+    let prev: BTreeMap<u64, Quantity> = prev.iter().copied().collect();
+    let curr: BTreeMap<u64, Quantity> = curr.iter().copied().collect();
+
+    let mut prices: Vec<u64> = prev.keys().chain(curr.keys()).copied().collect();
+    prices.sort_unstable();
+    prices.dedup();
+
+    prices
+        .into_iter()
+        .filter_map(|price| {
+            let change = match (prev.get(&price), curr.get(&price)) {
+                (None, Some(&quantity)) => LevelChange::Added(quantity),
+                (Some(&old), Some(&new)) if old != new => LevelChange::Updated(new),
+                (Some(_), None) => LevelChange::Removed,
+                _ => return None,
+            };
+            Some(LevelDelta { price, change })
+        })
+        .collect()
+}
+
+fn apply_level_deltas(levels: &mut Vec<Level>, deltas: &[LevelDelta], side: Side) {
+    let mut by_price: BTreeMap<u64, Quantity> = levels.iter().copied().collect();
+
+    for delta in deltas {
+        match delta.change {
+            LevelChange::Added(quantity) | LevelChange::Updated(quantity) => {
+                by_price.insert(delta.price, quantity);
+            }
+            LevelChange::Removed => {
+                by_price.remove(&delta.price);
+            }
+        }
+    }
+
+    *levels = match side {
+        Side::Buy => by_price.into_iter().rev().collect(),
+        Side::Sell => by_price.into_iter().collect(),
+    };
+}
+
+/// Summary of a book's state at one moment, as returned by
+/// `OrderBook::metrics`. Prices, levels and quantities only cover displayed
+/// orders.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookMetrics {
+    pub symbol: String,
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+    /// Best ask minus best bid, when both exist.
+    pub spread: Option<u64>,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub bid_quantity: Quantity,
+    pub ask_quantity: Quantity,
+    pub trade_count: usize,
+    pub total_volume: Quantity,
+    pub last_price: Option<u64>,
+}
+
+/// What `OrderBook::compact` cleaned up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompactionReport {
+    /// Price levels that held no orders.
+    pub empty_levels_removed: usize,
+    /// Id index entries for orders no longer resting.
+    pub stale_ids_removed: usize,
+    /// Resting orders missing from, or out of date in, the id index.
+    pub ids_repaired: usize,
+}
+
+/// Acknowledgement returned by `OrderBook::submit`.
+#[derive(Debug, Clone)]
+pub struct OrderOutcome {
+    pub order_id: String,
+    pub status: OrderStatus,
+    pub trades: Vec<Trade>,
+    pub resting_quantity: Quantity,
+}
+
+/// Self-trade prevention: what happens when an incoming order crosses a
+/// resting order from the same trader.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StpMode {
+    /// The orders trade with each other as usual.
+    #[default]
+    Allow,
+    /// The resting order is cancelled and matching continues past it.
+    CancelResting,
+    /// The rest of the incoming order is cancelled.
+    CancelIncoming,
+    /// Both orders shrink by the smaller of their quantities without a
+    /// trade; whichever reaches zero is removed.
+    DecrementBoth,
+}
+
+/// Whether an incoming limit order trades against a resting order at exactly
+/// its limit price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CrossRule {
+    /// Equal prices trade.
+    #[default]
+    Inclusive,
+    /// Only strictly better prices trade; an order at an equal price rests
+    /// beside it, locking the book.
+    Strict,
+}
+
+/// What happens to the part of a market order the book cannot fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarketUnfilledPolicy {
+    /// The remainder is cancelled.
+    #[default]
+    Cancel,
+    /// The remainder rests as a limit order at the last fill price. With no
+    /// fill at all the order is rejected as under `Cancel`.
+    ConvertToLimit,
+    /// The order is rejected without trading unless the book can fill it
+    /// in full.
+    Reject,
+}
+
+/// Which price a trade prints at when an incoming order crosses a resting one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TradePricePolicy {
+    /// The resting order's price.
+    #[default]
+    Maker,
+    /// The incoming order's limit price.
+    Aggressor,
+    /// Halfway between the two, rounded with the book's `RoundingMode`. For
+    /// the incoming order this is worse than the resting price; see
+    /// `OrderBook::set_price_improvement` for a better one.
+    Midpoint,
+}
+
+impl TradePricePolicy {
+    /// The trade price for `aggressor` crossing a resting order at
+    /// `maker_price`. Market orders have no limit, so they always trade at
+    /// the maker's price.
+    fn price(self, maker_price: u64, aggressor: &Order, rounding_mode: RoundingMode) -> u64 {
+        if aggressor.order_type == OrderType::Market {
+            return maker_price;
+        }
+
+        match self {
+            TradePricePolicy::Maker => maker_price,
+            TradePricePolicy::Aggressor => aggressor.price,
+            TradePricePolicy::Midpoint => {
+                rounding_mode.divide(maker_price as u128 + aggressor.price as u128, 2) as u64
+            }
+        }
+    }
+}
+
+/// How a division that does not come out even is rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// Round towards zero.
+    Floor,
+    /// Round away from zero.
+    Ceil,
+    /// Round to the nearest integer, halves away from zero.
+    #[default]
+    HalfUp,
+}
+
+impl RoundingMode {
+    /// Divides `numerator` by a non-zero `denominator`, rounding the result.
+    pub fn divide(self, numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+
+        let round_up = match self {
+            RoundingMode::Floor => false,
+            RoundingMode::Ceil => remainder > 0,
+            RoundingMode::HalfUp => remainder >= denominator - remainder,
+        };
+
+        if round_up { quotient + 1 } else { quotient }
+    }
+
+    /// Rounds `price` to a multiple of a non-zero `tick`.
+    pub fn round_to_tick(self, price: u64, tick: u64) -> u64 {
+        let ticks = self.divide(price as u128, tick as u128);
+        u64::try_from(ticks * tick as u128).unwrap_or(u64::MAX - u64::MAX % tick)
+    }
+}
+
+/// Hands out ids for the orders a book accepts.
+pub trait IdGenerator: Send + Sync {
+    fn next_id(&mut self) -> String;
+}
+
+/// Produces `<prefix>-1`, `<prefix>-2` and so on.
+#[derive(Debug, Clone)]
+pub struct SequentialIdGenerator {
+    prefix: String,
+    next: u64,
+}
+
+impl SequentialIdGenerator {
+    pub fn new(prefix: &str) -> Self {
+        SequentialIdGenerator {
+            prefix: prefix.to_string(),
+            next: 1,
+        }
+    }
+}
+
+impl Default for SequentialIdGenerator {
+    fn default() -> Self {
+        SequentialIdGenerator::new("ORD")
+    }
+}
+
+impl IdGenerator for SequentialIdGenerator {
+    fn next_id(&mut self) -> String {
+        let id = format!("{}-{}", self.prefix, self.next);
+        self.next += 1;
+        id
+    }
+}
+
+/// The reference price a pegged order tracks, taken from displayed orders
+/// only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PegType {
+    BestBid,
+    BestAsk,
+    /// The midpoint of the best bid and best ask, rounded down.
+    Mid,
+}
+
+/// Which market price pending stop orders are triggered by. Book prices
+/// only count displayed orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StopTriggerRef {
+    /// The price of the most recent trade.
+    #[default]
+    LastTrade,
+    /// The midpoint of the best bid and best ask, rounded down.
+    Mid,
+    BestBid,
+    BestAsk,
+}
+
+/// How the best bid and best ask of a book relate to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookState {
+    /// Neither side has any displayed orders.
+    Empty,
+    /// Only one side has displayed orders.
+    OneSided,
+    /// The best bid is below the best ask.
+    Normal,
+    /// The best bid equals the best ask.
+    Locked,
+    /// The best bid is above the best ask.
+    Crossed,
+}
+
+pub struct OrderBook {
+    symbol: String,
+    buy_orders: BTreeMap<u64, VecDeque<Order>>,
+    sell_orders: BTreeMap<u64, VecDeque<Order>>,
+    orders_by_id: HashMap<String, Order>,
+    trades: Vec<Trade>,
+    trade_pool: Option<Vec<Trade>>,
+    fills_by_order: HashMap<String, Vec<usize>>,
+    rejected: Vec<(Order, OrderError)>,
+    max_orders_per_side: Option<usize>,
+    trade_sender: Option<Sender<Trade>>,
+    expiries: BinaryHeap<(Reverse<DateTime<Utc>>, String)>,
+    pending_stops: Vec<Order>,
+    stop_trigger_ref: StopTriggerRef,
+    last_trade_price: Option<u64>,
+    next_seq: u64,
+    next_queue_jump: u64,
+    pegged_ids: HashSet<String>,
+    total_volume: Quantity,
+    trade_count: usize,
+    rounding_mode: RoundingMode,
+    fee_bps: u32,
+    trade_price_policy: TradePricePolicy,
+    price_improvement: bool,
+    record_trades: bool,
+    stp_mode: StpMode,
+    market_unfilled_policy: MarketUnfilledPolicy,
+    cross_rule: CrossRule,
+    max_market_spread_bps: Option<u32>,
+    tick_size: u64,
+    price_scale: u32,
+    max_match_steps: Option<usize>,
+    fill_model: Option<(f64, StdRng)>,
+    cancel_on_step_limit: bool,
+    rate_limit: Option<(u32, Duration)>,
+    recent_submissions: HashMap<String, VecDeque<DateTime<Utc>>>,
+    clock: Box<dyn Fn() -> DateTime<Utc> + Send + Sync>,
+    trader_priority: HashMap<String, u8>,
+    id_generator: Option<Box<dyn IdGenerator>>,
+    amendments: Vec<Amendment>,
+    snapshots: Vec<(DateTime<Utc>, BookSnapshot)>,
+    positions: HashMap<String, i128>,
+}
+
+impl OrderBook {
+    /// Creates an empty book. The symbol is trimmed and uppercased, the same
+    /// way order symbols are.
+    pub fn new(symbol: String) -> Self {
+        OrderBook {
+            symbol: normalize_symbol(&symbol),
+            buy_orders: BTreeMap::new(),
+            sell_orders: BTreeMap::new(),
+            orders_by_id: HashMap::new(),
+            trades: Vec::new(),
+            trade_pool: None,
+            fills_by_order: HashMap::new(),
+            rejected: Vec::new(),
+            max_orders_per_side: None,
+            trade_sender: None,
+            expiries: BinaryHeap::new(),
+            pending_stops: Vec::new(),
+            stop_trigger_ref: StopTriggerRef::default(),
+            last_trade_price: None,
+            next_seq: 1,
+            next_queue_jump: 1,
+            pegged_ids: HashSet::new(),
+            total_volume: Quantity::ZERO,
+            trade_count: 0,
+            rounding_mode: RoundingMode::default(),
+            fee_bps: 0,
+            trade_price_policy: TradePricePolicy::default(),
+            price_improvement: false,
+            record_trades: true,
+            stp_mode: StpMode::default(),
+            market_unfilled_policy: MarketUnfilledPolicy::default(),
+            cross_rule: CrossRule::default(),
+            max_market_spread_bps: None,
+            tick_size: 1,
+            price_scale: 0,
+            max_match_steps: None,
+            fill_model: None,
+            cancel_on_step_limit: false,
+            rate_limit: None,
+            recent_submissions: HashMap::new(),
+            clock: Box::new(Utc::now),
+            trader_priority: HashMap::new(),
+            id_generator: None,
+            amendments: Vec::new(),
+            snapshots: Vec::new(),
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Creates an empty book that pools trade records, for high-frequency
+    /// simulations. Otherwise the same as `new`.
+    ///
+    /// Trades handed back through `recycle_trades` are kept in a pool, and
+    /// new trades, including the copies kept in the trade history, are
+    /// written into pooled ones, reusing their string buffers instead of
+    /// allocating. The trade history also has room for `trade_capacity`
+    /// trades up front.
+    pub fn with_trade_pool(symbol: String, trade_capacity: usize) -> Self {
+        let mut book = OrderBook::new(symbol);
+        book.trades.reserve(trade_capacity);
+        book.trade_pool = Some(Vec::with_capacity(trade_capacity));
+        book
+    }
+
+    /// Hands trades the caller is done with, e.g. from `submit` or
+    /// `drain_trades`, back to a pooling book for reuse. A book created
+    /// without `with_trade_pool` just drops them.
+    pub fn recycle_trades(&mut self, trades: impl IntoIterator<Item = Trade>) {
+        if let Some(pool) = &mut self.trade_pool {
+            pool.extend(trades);
+        }
+    }
+
+    /// Builds a book straight from a set of resting limit orders, without
+    /// matching them. Orders keep the given order for time priority.
+    ///
+    /// Fails with `OrderError::CrossedBook` if the orders would cross or lock
+    /// the book, or with the error `add_for_auction` gives for an order, e.g.
+    /// `OrderError::DuplicateOrderId` when two orders share an id.
+    pub fn from_resting_orders(symbol: String, orders: Vec<Order>) -> Result<OrderBook, OrderError> {
+        let mut book = OrderBook::new(symbol);
+        for order in orders {
+            book.add_for_auction(order)?;
+        }
+
+        // Hidden orders would trade too, so they count here
+        match (book.top_price(Side::Buy), book.top_price(Side::Sell)) {
+            (Some(best_bid), Some(best_ask)) if best_bid >= best_ask => Err(OrderError::CrossedBook),
+            _ => Ok(book),
+        }
+    }
+
+    /// Replays `orders` through a fresh book for `symbol` and checks that it
+    /// produces `expected_trades`, in order. Trades are compared on their
+    /// economic content only, not their ids or timestamps. The error
+    /// describes the first difference.
+    pub fn validate_against_log(symbol: String, orders: &[Order], expected_trades: &[Trade]) -> Result<(), String> {
+        let mut book = OrderBook::new(symbol);
+        for order in orders {
+            book.submit(order.clone());
+        }
+        let trades = book.drain_trades();
+
+        for (index, (expected, actual)) in expected_trades.iter().zip(&trades).enumerate() {
+            if !expected.same_execution(actual) {
+                return Err(format!(
+                    "trade {}: expected {} @ {} (buy {}, sell {}), got {} @ {} (buy {}, sell {})",
+                    index,
+                    expected.quantity,
+                    expected.price,
+                    expected.buy_order_id,
+                    expected.sell_order_id,
+                    actual.quantity,
+                    actual.price,
+                    actual.buy_order_id,
+                    actual.sell_order_id,
+                ));
+            }
+        }
+        if trades.len() != expected_trades.len() {
+            return Err(format!("expected {} trades, got {}", expected_trades.len(), trades.len()));
+        }
+
+        Ok(())
+    }
+
+    /// Chooses how integer divisions (fees, VWAP, spread in basis points) are
+    /// rounded. Defaults to `RoundingMode::HalfUp`.
+    pub fn set_rounding_mode(&mut self, rounding_mode: RoundingMode) {
+        self.rounding_mode = rounding_mode;
+    }
+
+    /// Sets the fee charged on each trade's notional, in basis points.
+    pub fn set_fee_bps(&mut self, fee_bps: u32) {
+        self.fee_bps = fee_bps;
+    }
+
+    /// Chooses whether executed trades are kept in the book's trade history.
+    /// On by default.
+    ///
+    /// Turning it off saves a clone of every trade for callers that only use
+    /// the trades returned from `place_order`/`submit`. History-based queries
+    /// such as `trades_for` and `vwap` then only see trades recorded while it
+    /// was on; `total_volume` and `trade_count` are unaffected.
+    pub fn set_record_trades(&mut self, record_trades: bool) {
+        self.record_trades = record_trades;
+    }
+
+    /// Chooses what happens when an incoming order would trade against a
+    /// resting order from the same trader. Defaults to `StpMode::Allow`.
+    pub fn set_stp_mode(&mut self, stp_mode: StpMode) {
+        self.stp_mode = stp_mode;
+    }
+
+    /// Chooses what happens to the part of a market order that cannot be
+    /// filled. Defaults to `MarketUnfilledPolicy::Cancel`.
+    pub fn set_market_unfilled_policy(&mut self, policy: MarketUnfilledPolicy) {
+        self.market_unfilled_policy = policy;
+    }
+
+    /// Sets the price increment trades print on. A trade price that falls
+    /// between ticks, e.g. a `TradePricePolicy::Midpoint` price, is rounded
+    /// to a tick with the book's `RoundingMode`, as long as the rounded price
+    /// still lies between the two orders' prices. Defaults to 1; zero is
+    /// treated as 1.
+    pub fn set_tick_size(&mut self, tick_size: u64) {
+        self.tick_size = tick_size.max(1);
+    }
+
+    /// Caps how many fills one incoming order may make. Once it reaches `max`
+    /// the order stops matching and its remainder is handled as if the book
+    /// had run out: it rests, unless cancelled by `set_cancel_on_step_limit`.
+    /// A limit remainder rested this way can leave the book crossed until
+    /// later orders trade it away. `None` removes the cap.
+    pub fn set_max_match_steps(&mut self, max: Option<usize>) {
+        self.max_match_steps = max;
+    }
+
+    /// Makes each encounter between an incoming order and a resting one fill
+    /// only with `probability` (clamped to `0.0..=1.0`), drawn from an RNG
+    /// seeded with `seed`. A resting order that misses is passed over for the
+    /// rest of that incoming order, whose remainder may then rest across it.
+    /// `None`, the default, fills every encounter.
+    pub fn set_fill_probability(&mut self, probability: Option<f64>, seed: u64) {
+        self.fill_model = probability
+            .filter(|probability| !probability.is_nan())
+            .map(|probability| (probability.clamp(0.0, 1.0), StdRng::seed_from_u64(seed)));
+    }
+
+    /// Cancels, rather than rests, the remainder of an order stopped by
+    /// `set_max_match_steps`. Off by default.
+    pub fn set_cancel_on_step_limit(&mut self, cancel: bool) {
+        self.cancel_on_step_limit = cancel;
+    }
+
+    /// Sets how many decimal places of a price are minor units when the book
+    /// is displayed; prices are still stored as integers. Defaults to 0.
+    /// Scales above 19 are treated as 19, the most a `u64` price can use.
+    pub fn set_price_scale(&mut self, price_scale: u32) {
+        self.price_scale = price_scale.min(19);
+    }
+
+    /// Rejects market orders with `OrderError::SpreadTooWide` while
+    /// `spread_bps` is above `max`. A book without both a bid and an ask has
+    /// no spread and never trips the check. `None` removes the limit.
+    pub fn set_max_market_spread_bps(&mut self, max: Option<u32>) {
+        self.max_market_spread_bps = max;
+    }
+
+    /// Chooses whether incoming limit orders trade at exactly their limit
+    /// price. Defaults to `CrossRule::Inclusive`.
+    pub fn set_cross_rule(&mut self, cross_rule: CrossRule) {
+        self.cross_rule = cross_rule;
+    }
+
+    /// Chooses which price trades print at. Defaults to
+    /// `TradePricePolicy::Maker`.
+    pub fn set_trade_price_policy(&mut self, policy: TradePricePolicy) {
+        self.trade_price_policy = policy;
+    }
+
+    /// Gives crossing orders price improvement: an incoming order trades
+    /// halfway between the resting price and the best displayed price on its
+    /// own side of the book, in whole ticks, whenever that beats the
+    /// `TradePricePolicy` price. An order with no quote on its own side, or
+    /// less than a tick of room, trades at the policy price. Off by default.
+    pub fn set_price_improvement(&mut self, enabled: bool) {
+        self.price_improvement = enabled;
+    }
+
+    /// Chooses the market price that pending stop orders trigger on.
+    /// Defaults to `StopTriggerRef::LastTrade`.
+    pub fn set_stop_trigger_ref(&mut self, trigger_ref: StopTriggerRef) {
+        self.stop_trigger_ref = trigger_ref;
+    }
+
+    /// Limits each trader to `max` orders per rolling `window`. Orders over
+    /// the limit are rejected with `OrderError::RateLimited` and do not count
+    /// towards it. `None` removes the limit.
+    pub fn set_rate_limit(&mut self, rate_limit: Option<(u32, Duration)>) {
+        self.rate_limit = rate_limit;
+    }
+
+    /// Gives `trader_id`'s orders priority over lower priority traders'
+    /// orders at the same price; equal priorities keep time priority. Traders
+    /// default to priority 0. Resting orders are requeued straight away.
+    pub fn set_trader_priority(&mut self, trader_id: &str, priority: u8) {
+        self.trader_priority.insert(trader_id.to_string(), priority);
+
+        let trader_priority = &self.trader_priority;
+        for orders in self.buy_orders.values_mut().chain(self.sell_orders.values_mut()) {
+            orders
+                .make_contiguous()
+                .sort_by_key(|order| queue_key(order, trader_priority));
+        }
+    }
+
+    /// Restamps every order entering the book through `submit`,
+    /// `place_order` or `add_for_auction` with an id from `generator`. By
+    /// default orders keep the id they were created with.
+    pub fn set_id_generator(&mut self, generator: impl IdGenerator + 'static) {
+        self.id_generator = Some(Box::new(generator));
+    }
+
+    /// Replaces the clock the book reads the current time from. Defaults to
+    /// `Utc::now`. The clock stamps accepted orders and trades and drives the
+    /// rate limit.
+    pub fn set_clock(&mut self, clock: impl Fn() -> DateTime<Utc> + Send + Sync + 'static) {
+        self.clock = Box::new(clock);
+    }
+
+    /// Stop orders waiting for their trigger, in submission order.
+    pub fn pending_stops(&self) -> &[Order] {
+        &self.pending_stops
+    }
+
+    /// Also sends every executed trade down `tx` as it happens.
+    ///
+    /// A disconnected receiver is ignored; matching carries on regardless.
+    pub fn with_trade_sender(&mut self, tx: Sender<Trade>) {
+        self.trade_sender = Some(tx);
+    }
+
+    /// Limits how many orders may rest on each side of the book.
+    ///
+    /// Once a side holds `max` orders, incoming orders can still match against
+    /// the book but any remainder that would rest is rejected with
+    /// `OrderError::BookFull`. `None` removes the limit.
+    pub fn set_max_orders_per_side(&mut self, max: Option<usize>) {
+        self.max_orders_per_side = max;
+    }
+
+    /// Places an order and returns the trades it produced.
+    ///
+    /// An order the book refuses produces no trades; the reason is recorded
+    /// in `rejected_orders`. Use `submit` to get the reason directly.
+    pub fn place_order(&mut self, order: Order) -> Vec<Trade> {
+        self.submit(order).trades
+    }
+
+    /// Places an order and reports what happened to it.
+    ///
+    /// An invalid order, e.g. one for another symbol or with zero quantity,
+    /// is reported as `OrderStatus::Rejected` and logged in
+    /// `rejected_orders`. An order whose remainder could not rest is also
+    /// `Rejected`, but keeps the trades it produced.
+    /// A market order that only partly filled is `PartiallyFilled`, resting
+    /// nothing unless the `MarketUnfilledPolicy` converts it to a limit. A
+    /// stop order is parked as `Pending` until triggered.
+    ///
+    /// After the order is processed, pending stops are checked against the
+    /// configured `StopTriggerRef`. Stops triggered this way are submitted in
+    /// turn, and pegged orders are then moved to their new reference prices.
+    /// Trades from either appear in the book's trade history, not in the
+    /// returned outcome.
+    pub fn submit(&mut self, mut order: Order) -> OrderOutcome {
+        self.stamp_id(&mut order);
+
+        if let Err(error) = self.check_rate_limit(&order.trader_id) {
+            return OrderOutcome {
+                order_id: order.id.clone(),
+                status: OrderStatus::Rejected(self.reject(order, error)),
+                trades: Vec::new(),
+                resting_quantity: Quantity::ZERO,
+            };
+        }
+
+        let outcome = self.process(order);
+        self.trigger_stops();
+        self.reprice_pegs();
+        outcome
+    }
+
+    fn process(&mut self, mut order: Order) -> OrderOutcome {
+        let order_id = order.id.clone();
+
+        if let Err(error) = self.validate(&order) {
+            return OrderOutcome {
+                order_id,
+                status: OrderStatus::Rejected(self.reject(order, error)),
+                trades: Vec::new(),
+                resting_quantity: Quantity::ZERO,
+            };
+        }
+
+        order.seq = self.next_seq;
+        self.next_seq += 1;
+        order.timestamp = (self.clock)();
+        order.queue_jump = 0;
+
+        if let Some(price) = self.peg_price(&order) {
+            order.price = price;
+        }
+
+        if order.stop_price.is_some() {
+            self.pending_stops.push(order);
+            return OrderOutcome {
+                order_id,
+                status: OrderStatus::Pending,
+                trades: Vec::new(),
+                resting_quantity: Quantity::ZERO,
+            };
+        }
+
+        if order.reduce_only {
+            let closable = match self.position(&order.trader_id) {
+                Some((side, size)) if side == order.side.opposite() => size,
+                _ => Quantity::ZERO,
+            };
+            if closable.is_zero() {
+                return OrderOutcome {
+                    order_id,
+                    status: OrderStatus::Rejected(self.reject(order, OrderError::NoPositionToReduce)),
+                    trades: Vec::new(),
+                    resting_quantity: Quantity::ZERO,
+                };
+            }
+            order.quantity = order.quantity.min(closable);
+        }
+
+        let quantity = order.quantity;
+        let (trades, rested) = self.execute(order);
+        let filled_quantity: Quantity = trades.iter().map(|trade| trade.quantity).sum();
+
+        let (status, resting_quantity) = match rested {
+            Err(error) => (OrderStatus::Rejected(error), Quantity::ZERO),
+            Ok(_) if filled_quantity == quantity => (OrderStatus::Filled, Quantity::ZERO),
+            Ok(Quantity::ZERO) if filled_quantity.is_zero() => (OrderStatus::Cancelled, Quantity::ZERO),
+            Ok(resting) if filled_quantity.is_zero() => (OrderStatus::Resting, resting),
+            Ok(resting) => (OrderStatus::PartiallyFilled, resting),
+        };
+
+        OrderOutcome {
+            order_id,
+            status,
+            trades,
+            resting_quantity,
+        }
+    }
+
+    /// Moves every resting pegged order whose reference price has changed to
+    /// its new price. A moved order goes through matching again and joins the
+    /// back of its new level.
+    fn reprice_pegs(&mut self) {
+        // Forget pegged orders that have since filled or been cancelled
+        let orders_by_id = &self.orders_by_id;
+        self.pegged_ids.retain(|order_id| orders_by_id.contains_key(order_id));
+
+        let mut moves: Vec<(u64, String, u64)> = self
+            .pegged_ids
+            .iter()
+            .filter_map(|order_id| {
+                let order = &self.orders_by_id[order_id];
+                let price = self.peg_price(order)?;
+                (price != order.price).then(|| (order.seq, order.id.clone(), price))
+            })
+            .collect();
+        moves.sort();
+
+        for (_, order_id, price) in moves {
+            if let Ok(mut order) = self.remove_order(&order_id) {
+                order.price = price;
+                self.process(order);
+            }
+        }
+    }
+
+    /// The price a pegged order should currently rest at, or `None` if the
+    /// order is not pegged or its reference is unavailable.
+    ///
+    /// References ignore other pegged orders, so pegs track the price set by
+    /// ordinary orders rather than each other.
+    fn peg_price(&self, order: &Order) -> Option<u64> {
+        let reference = match order.peg? {
+            PegType::BestBid => self.unpegged_best(Side::Buy)?,
+            PegType::BestAsk => self.unpegged_best(Side::Sell)?,
+            PegType::Mid => (self.unpegged_best(Side::Buy)? + self.unpegged_best(Side::Sell)?) / 2,
+        };
+
+        let price = reference.saturating_add_signed(order.peg_offset);
+        Some(price.max(1))
+    }
+
+    /// Best price on `side` among displayed orders that are not pegged.
+    fn unpegged_best(&self, side: Side) -> Option<u64> {
+        let has_unpegged = |orders: &VecDeque<Order>| orders.iter().any(|order| order.peg.is_none() && !order.hidden);
+
+        match side {
+            Side::Buy => self.buy_orders.iter().rev().find(|(_, orders)| has_unpegged(orders)),
+            Side::Sell => self.sell_orders.iter().find(|(_, orders)| has_unpegged(orders)),
+        }
+        .map(|(&price, _)| price)
+    }
+
+    /// Submits every pending stop whose trigger has been reached, repeating
+    /// until no more fire since each triggered stop can move the market.
+    fn trigger_stops(&mut self) {
+        while let Some(index) = self.pending_stops.iter().position(|stop| self.is_triggered(stop)) {
+            let mut order = self.pending_stops.remove(index);
+            order.stop_price = None;
+            self.process(order);
+        }
+    }
+
+    fn is_triggered(&self, stop: &Order) -> bool {
+        let (Some(stop_price), Some(reference)) = (stop.stop_price, self.stop_reference_price()) else {
+            return false;
+        };
+
+        match stop.side {
+            Side::Buy => reference >= stop_price,
+            Side::Sell => reference <= stop_price,
+        }
+    }
+
+    /// The price pending stops are compared against, per `StopTriggerRef`.
+    fn stop_reference_price(&self) -> Option<u64> {
+        match self.stop_trigger_ref {
+            StopTriggerRef::LastTrade => self.last_trade_price,
+            StopTriggerRef::Mid => Some((self.get_best_bid()? + self.get_best_ask()?) / 2),
+            StopTriggerRef::BestBid => self.get_best_bid(),
+            StopTriggerRef::BestAsk => self.get_best_ask(),
+        }
+    }
+
+    /// Gives an incoming order an id from the book's generator, if it has one.
+    fn stamp_id(&mut self, order: &mut Order) {
+        if let Some(generator) = &mut self.id_generator {
+            order.id = generator.next_id();
+        }
+    }
+
+    /// Counts a submission from `trader_id` against the rate limit, after
+    /// dropping submissions that have left the window.
+    fn check_rate_limit(&mut self, trader_id: &str) -> Result<(), OrderError> {
+        let Some((max, window)) = self.rate_limit else {
+            return Ok(());
+        };
+
+        let now = (self.clock)();
+        let submissions = self.recent_submissions.entry(trader_id.to_string()).or_default();
+        while let Some(&submitted_at) = submissions.front() {
+            match now.signed_duration_since(submitted_at).to_std() {
+                Ok(age) if age >= window => {
+                    submissions.pop_front();
+                }
+                _ => break,
+            }
+        }
+
+        if submissions.len() >= max as usize {
+            return Err(OrderError::RateLimited);
+        }
+        submissions.push_back(now);
+        Ok(())
+    }
+
+    /// Checks an incoming order before it is allowed to match.
+    fn validate(&self, order: &Order) -> Result<(), OrderError> {
+        if order.symbol.is_empty() {
+            return Err(OrderError::EmptySymbol);
+        }
+        if order.trader_id.trim().is_empty() {
+            return Err(OrderError::EmptyTraderId);
+        }
+        if order.symbol != self.symbol {
+            return Err(OrderError::SymbolMismatch);
+        }
+        if order.quantity.is_zero() {
+            return Err(OrderError::ZeroQuantity);
+        }
+        self.check_unique_ids(order)
+    }
+
+    /// Checks that no resting or pending order already uses `order`'s id, or
+    /// its client order id for the same trader. Pegged and triggered stop
+    /// orders leave the book before they are processed again, so they never
+    /// clash with themselves.
+    fn check_unique_ids(&self, order: &Order) -> Result<(), OrderError> {
+        if self.orders_by_id.contains_key(&order.id) || self.pending_stops.iter().any(|stop| stop.id == order.id) {
+            return Err(OrderError::DuplicateOrderId);
+        }
+        if let Some(client_id) = order.client_order_id.as_deref() {
+            if self.find_by_client_id(&order.trader_id, client_id).is_some() {
+                return Err(OrderError::DuplicateClientOrderId);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records `order` in the rejected-orders log and hands back the reason.
+    fn reject(&mut self, order: Order, error: OrderError) -> OrderError {
+        self.rejected.push((order, error.clone()));
+        error
+    }
+
+    /// Submits every resting order of `other` into this book through normal
+    /// matching, oldest first, and returns the trades the combined liquidity
+    /// produced. Orders with the same timestamp keep `other`'s arrival order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the two books are for different symbols.
+    pub fn merge(&mut self, other: OrderBook) -> Vec<Trade> {
+        if other.symbol != self.symbol {
+            panic!("Cannot merge order book for {} into order book for {}", other.symbol, self.symbol);
+        }
+
+        let mut orders: Vec<Order> = other.orders_by_id.into_values().collect();
+        orders.sort_by_key(|order| (order.timestamp, order.seq));
+
+        let mut trades = Vec::new();
+        for order in orders {
+            trades.extend(self.submit(order).trades);
+        }
+        trades
+    }
+
+    /// Adds a limit order to the book without matching it, so that crossing
+    /// orders can build up for a later `uncross`.
+    pub fn add_for_auction(&mut self, mut order: Order) -> Result<(), OrderError> {
+        self.stamp_id(&mut order);
+
+        if let Err(error) = self.validate(&order) {
+            return Err(self.reject(order, error));
+        }
+        if order.order_type != OrderType::Limit || order.stop_price.is_some() {
+            return Err(self.reject(order, OrderError::UnsupportedOrderType));
+        }
+
+        order.seq = self.next_seq;
+        self.next_seq += 1;
+        order.timestamp = (self.clock)();
+        order.queue_jump = 0;
+        self.add_order(order);
+        Ok(())
+    }
+
+    /// Runs a single-price auction on a crossed book.
+    ///
+    /// The clearing price is the price that maximizes matched volume; ties
+    /// go to the price leaving the smallest unmatched surplus, then to the
+    /// lowest price. All crossing orders then trade at that one price, bids
+    /// from the highest and asks from the lowest, in time priority within a
+    /// level. Returns `(None, [])` if the book does not cross.
+    pub fn uncross(&mut self) -> (Option<u64>, Vec<Trade>) {
+        let Some((clearing_price, volume)) = self.clearing_price() else {
+            return (None, Vec::new());
+        };
+
+        let mut trades = Vec::new();
+        let mut remaining_volume = volume;
+        let now = (self.clock)();
+
+        while !remaining_volume.is_zero() {
+            let (Some(bid_price), Some(ask_price)) = (self.top_price(Side::Buy), self.top_price(Side::Sell)) else {
+                break;
+            };
+
+            let bids = self.buy_orders.get_mut(&bid_price).unwrap();
+            let asks = self.sell_orders.get_mut(&ask_price).unwrap();
+            let (Some(bid_index), Some(ask_index)) = (next_to_match(bids, |_| true), next_to_match(asks, |_| true)) else {
+                break;
+            };
+
+            let (bid, ask) = (&mut bids[bid_index], &mut asks[ask_index]);
+            let trade_quantity = remaining_volume.min(bid.quantity).min(ask.quantity);
+            let aggressor = if bid.seq > ask.seq { Side::Buy } else { Side::Sell };
+            let trade = pooled_trade(&mut self.trade_pool, bid, ask, clearing_price, trade_quantity, aggressor, now);
+
+            remaining_volume = reduce_quantity(remaining_volume, trade_quantity);
+            bid.quantity = reduce_quantity(bid.quantity, trade_quantity);
+            ask.quantity = reduce_quantity(ask.quantity, trade_quantity);
+            settle_resting_order(bids, bid_index, &mut self.orders_by_id);
+            settle_resting_order(asks, ask_index, &mut self.orders_by_id);
+
+            if bids.is_empty() {
+                self.buy_orders.remove(&bid_price);
+            }
+            if asks.is_empty() {
+                self.sell_orders.remove(&ask_price);
+            }
+
+            self.last_trade_price = Some(trade.price);
+            self.total_volume += trade.quantity;
+            self.trade_count += 1;
+            shift_positions(&mut self.positions, &trade, 1);
+            if let Some(tx) = &self.trade_sender {
+                let _ = tx.send(pooled_copy(&mut self.trade_pool, &trade));
+            }
+            trades.push(trade);
+        }
+
+        self.record(&trades);
+
+        self.trigger_stops();
+        self.reprice_pegs();
+
+        (Some(clearing_price), trades)
+    }
+
+    /// The auction clearing price and the volume that trades at it, or
+    /// `None` if no bid crosses any ask.
+    fn clearing_price(&self) -> Option<(u64, Quantity)> {
+        let total_quantity = |orders: &VecDeque<Order>| orders.iter().map(|order| order.quantity).sum::<Quantity>();
+
+        let mut best: Option<(u64, Quantity, Quantity)> = None;
+        for &price in self.buy_orders.keys().chain(self.sell_orders.keys()) {
+            let demand: Quantity = self.buy_orders.range(price..).map(|(_, orders)| total_quantity(orders)).sum();
+            let supply: Quantity = self.sell_orders.range(..=price).map(|(_, orders)| total_quantity(orders)).sum();
+            let volume = demand.min(supply);
+            let surplus = demand.max(supply) - volume;
+
+            if volume.is_zero() {
+                continue;
+            }
+
+            let better = match best {
+                None => true,
+                Some((best_price, best_volume, best_surplus)) => {
+                    (volume, Reverse(surplus), Reverse(price))
+                        > (best_volume, Reverse(best_surplus), Reverse(best_price))
+                }
+            };
+            if better {
+                best = Some((price, volume, surplus));
+            }
+        }
+
+        best.map(|(price, volume, _)| (price, volume))
+    }
+
+    /// Splits `parent` into `slices` child orders and submits them one
+    /// `interval` apart, returning the trades of all of them. The children
+    /// share the parent's terms under new ids. Each gets the same number of
+    /// whole units and the last one also takes whatever does not divide
+    /// evenly, so 10 in 3 slices is 3, 3 and 4.
+    ///
+    /// The first child goes in at the book clock's current time. The clock is
+    /// then stepped forward by `interval` for each later child, so children
+    /// and their trades are stamped with the slicer's schedule, and restored
+    /// afterwards.
+    pub fn place_sliced(&mut self, parent: Order, slices: usize, interval: Duration) -> Vec<Trade> {
+        // Never more children than whole units of quantity
+        let units = parent.quantity.raw() / Quantity::UNIT;
+        let slices = (slices as u64).min(units).max(1);
+        let slice_quantity = Quantity::from_raw(units / slices * Quantity::UNIT);
+        let interval = chrono::Duration::from_std(interval).unwrap_or(chrono::Duration::MAX);
+        let start = (self.clock)();
+
+        let mut trades = Vec::new();
+        let mut remaining = parent.quantity;
+        for i in 0..slices {
+            let quantity = if i + 1 == slices { remaining } else { slice_quantity };
+            remaining -= quantity;
+            let due = i32::try_from(i)
+                .ok()
+                .and_then(|i| interval.checked_mul(i))
+                .and_then(|offset| start.checked_add_signed(offset))
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+            // Children are orders of their own, so they do not inherit the client tag
+            let child = Order {
+                id: Uuid::new_v4().to_string(),
+                quantity,
+                client_order_id: None,
+                ..parent.clone()
+            };
+            let clock = std::mem::replace(&mut self.clock, Box::new(move || due));
+            trades.extend(self.submit(child).trades);
+            self.clock = clock;
+        }
+
+        trades
+    }
+
+    /// Trades a `side` order for everything on the opposite side up to
+    /// `limit_price`, sized with `available_quantity_against`. Any part that
+    /// does not fill, e.g. because of self-trade prevention, is cancelled
+    /// rather than left resting.
+    pub fn sweep_to_price(&mut self, trader_id: &str, side: Side, limit_price: u64) -> Vec<Trade> {
+        let quantity = self.available_quantity_against(side, limit_price);
+        if quantity.is_zero() {
+            return Vec::new();
+        }
+
+        let order = Order::new(trader_id.to_string(), self.symbol.clone(), limit_price, quantity, side);
+        let outcome = self.submit(order);
+        if !outcome.resting_quantity.is_zero() {
+            let _ = self.cancel_order(&outcome.order_id);
+        }
+
+        outcome.trades
+    }
+
+    /// Total resting quantity, hidden orders included, that a `side` limit
+    /// order at `limit_price` could trade against.
+    pub fn available_quantity_against(&self, side: Side, limit_price: u64) -> Quantity {
+        self.levels(side.opposite())
+            .iter()
+            .filter(|&(&price, _)| crosses(side, limit_price, price, self.cross_rule))
+            .flat_map(|(_, orders)| orders.iter().map(|order| order.quantity))
+            .sum()
+    }
+
+    /// Submits a buy at `bid_price` and a sell at `ask_price`, both for `size`.
+    ///
+    /// A locked or crossed quote (`bid_price >= ask_price`) is rejected before
+    /// either leg is sent.
+    pub fn quote(&mut self, trader_id: &str, bid_price: u64, ask_price: u64, size: Quantity) -> Result<(OrderOutcome, OrderOutcome), OrderError> {
+        if bid_price >= ask_price {
+            return Err(OrderError::CrossedQuote);
+        }
+
+        let bid = Order::new(trader_id.to_string(), self.symbol.clone(), bid_price, size, Side::Buy);
+        let ask = Order::new(trader_id.to_string(), self.symbol.clone(), ask_price, size, Side::Sell);
+
+        let bid_outcome = self.submit(bid);
+        let ask_outcome = self.submit(ask);
+
+        Ok((bid_outcome, ask_outcome))
+    }
+
+    /// Matches `order` against the book and rests any remainder, returning the
+    /// resulting trades and either the quantity left resting or the reason the
+    /// remainder was refused.
+    fn execute(&mut self, order: Order) -> (Vec<Trade>, Result<Quantity, OrderError>) {
+        let mut trades = Vec::new();
+
+        if order.order_type == OrderType::Market {
+            if let (Some(max), Some(spread)) = (self.max_market_spread_bps, self.spread_bps()) {
+                if spread > max {
+                    return (trades, Err(self.reject(order, OrderError::SpreadTooWide)));
+                }
+            }
+        }
+        if order.order_type == OrderType::Market
+            && self.market_unfilled_policy == MarketUnfilledPolicy::Reject
+            && self.matchable_quantity(&order) < order.quantity
+        {
+            return (trades, Err(self.reject(order, OrderError::InsufficientLiquidity)));
+        }
+        if let Some(min_exec_qty) = order.min_exec_qty {
+            if self.matchable_quantity(&order) < min_exec_qty.min(order.quantity) {
+                return (trades, Err(self.reject(order, OrderError::InsufficientLiquidity)));
+            }
+        }
+
+        let mut remaining_order = order;
+        // The minimum only applies on entry, not when a pegged remainder is repriced
+        remaining_order.min_exec_qty = None;
+        let mut first_fill_price = None;
+        let now = (self.clock)();
+        // Whether self-trade prevention handled a crossing without a trade
+        let mut self_trade_prevented = false;
+        // Levels up to this price only hold all-or-none orders too big to take
+        let mut passed_price = None;
+        let mut step_limited = false;
+        // Resting orders the fill model let this order miss
+        let mut missed = HashSet::new();
+        // The own-side quote that price improvement splits the spread with
+        let own_best = match remaining_order.side {
+            _ if !self.price_improvement => None,
+            Side::Buy => self.get_best_bid(),
+            Side::Sell => self.get_best_ask(),
+        };
+
+        // Try to match with existing orders on the opposite side
+        while !remaining_order.quantity.is_zero() && !step_limited {
+            // Get the best opposing price: lowest sell for a buy, highest buy for a sell
+            let (best_price_opt, levels) = match (remaining_order.side, passed_price) {
+                (Side::Buy, None) => (self.sell_orders.keys().next().cloned(), &mut self.sell_orders),
+                (Side::Buy, Some(passed)) => (
+                    self.sell_orders.range((Bound::Excluded(passed), Bound::Unbounded)).next().map(|(&price, _)| price),
+                    &mut self.sell_orders,
+                ),
+                (Side::Sell, None) => (self.buy_orders.keys().next_back().cloned(), &mut self.buy_orders),
+                (Side::Sell, Some(passed)) => (
+                    self.buy_orders.range(..passed).next_back().map(|(&price, _)| price),
+                    &mut self.buy_orders,
+                ),
+            };
+
+            let best_price = match best_price_opt {
+                Some(price) if remaining_order.can_trade_at(price, self.cross_rule) => price,
+                _ => break, // No matching orders, or price is out of range
+            };
+
+            // Stop a protected market order before it walks too far from its first fill
+            let reference_price = *first_fill_price.get_or_insert(best_price);
+            if let Some(bps) = remaining_order.max_slippage_bps {
+                if exceeds_slippage(remaining_order.side, reference_price, best_price, bps) {
+                    break;
+                }
+            }
+
+            let trade_price = self.trade_price_policy.price(best_price, &remaining_order, self.rounding_mode);
+            let trade_price = on_tick(trade_price, best_price, &remaining_order, self.tick_size, self.rounding_mode);
+            let trade_price = improved_price(trade_price, best_price, own_best, remaining_order.side, self.tick_size);
+            let resting_orders = levels.get_mut(&best_price).unwrap();
+            
+            // Try to match with resting orders at this price level, in place
+            while !remaining_order.quantity.is_zero() {
+                if self.max_match_steps.is_some_and(|max| trades.len() >= max) {
+                    step_limited = true;
+                    break;
+                }
+
+                // Displayed orders go first; hidden ones only once none are left
+                let eligible = |order: &Order| {
+                    fits_all_or_none(order, remaining_order.quantity) && !missed.contains(&order.id)
+                };
+                let Some(index) = next_to_match(resting_orders, eligible) else {
+                    break;
+                };
+                let resting_order = &mut resting_orders[index];
+
+                // Self-trade prevention: handle the crossing without a trade
+                if self.stp_mode != StpMode::Allow && resting_order.trader_id == remaining_order.trader_id {
+                    self_trade_prevented = true;
+                    match self.stp_mode {
+                        StpMode::CancelIncoming => {
+                            remaining_order.quantity = Quantity::ZERO;
+                            break;
+                        }
+                        StpMode::CancelResting => resting_order.quantity = Quantity::ZERO,
+                        StpMode::DecrementBoth => {
+                            let overlap = std::cmp::min(remaining_order.quantity, resting_order.quantity);
+                            remaining_order.quantity = reduce_quantity(remaining_order.quantity, overlap);
+                            resting_order.quantity = reduce_quantity(resting_order.quantity, overlap);
+                        }
+                        StpMode::Allow => {}
+                    }
+                    settle_resting_order(resting_orders, index, &mut self.orders_by_id);
+                    continue;
+                }
+                
+                // Roll for whether this encounter fills at all
+                if let Some((probability, rng)) = &mut self.fill_model {
+                    if !rng.gen_bool(*probability) {
+                        missed.insert(resting_order.id.clone());
+                        continue;
+                    }
+                }
+
+                // Calculate trade quantity
+                let trade_quantity = std::cmp::min(remaining_order.quantity, resting_order.quantity);
+                
+                // Create trade
+                let (buy_order, sell_order) = match remaining_order.side {
+                    Side::Buy => (&remaining_order, &*resting_order),
+                    Side::Sell => (&*resting_order, &remaining_order),
+                };
+                let trade = pooled_trade(
+                    &mut self.trade_pool,
+                    buy_order,
+                    sell_order,
+                    trade_price,
+                    trade_quantity,
+                    remaining_order.side,
+                    now,
+                );
+                
+                self.last_trade_price = Some(trade.price);
+                self.total_volume += trade.quantity;
+                self.trade_count += 1;
+                shift_positions(&mut self.positions, &trade, 1);
+                if let Some(tx) = &self.trade_sender {
+                    let _ = tx.send(pooled_copy(&mut self.trade_pool, &trade));
+                }
+                trades.push(trade);
+                
+                // Update remaining quantities
+                remaining_order.quantity = reduce_quantity(remaining_order.quantity, trade_quantity);
+                resting_order.quantity = reduce_quantity(resting_order.quantity, trade_quantity);
+                
+                // Update or remove the matched resting order
+                settle_resting_order(resting_orders, index, &mut self.orders_by_id);
+            }
+            
+            // If no orders left at this price, remove the price level
+            if resting_orders.is_empty() {
+                levels.remove(&best_price);
+            } else if !remaining_order.quantity.is_zero() && !step_limited {
+                passed_price = Some(best_price);
+            }
+        }
+        
+        // If there's still quantity remaining, add it to its side of the book
+        let mut rested = Ok(Quantity::ZERO);
+        if !remaining_order.quantity.is_zero() {
+            if remaining_order.order_type == OrderType::Market
+                && self.market_unfilled_policy == MarketUnfilledPolicy::ConvertToLimit
+            {
+                if let Some(last_fill) = trades.last() {
+                    remaining_order.order_type = OrderType::Limit;
+                    remaining_order.price = last_fill.price;
+                }
+            }
+
+            let is_dust = !trades.is_empty()
+                && remaining_order.min_rest_qty.is_some_and(|min| remaining_order.quantity < min);
+
+            if remaining_order.order_type == OrderType::Market {
+                // Market orders never rest; the remainder is cancelled. With
+                // no trade and no self-trade prevented there was nothing to
+                // trade against at all.
+                if trades.is_empty() && !self_trade_prevented {
+                    rested = Err(self.reject(remaining_order, OrderError::NoLiquidity));
+                }
+            } else if is_dust {
+                // Cancel the leftover instead of resting an odd lot
+            } else if step_limited && self.cancel_on_step_limit {
+                // Drop what the step cap cut off
+            } else if self.is_side_full(remaining_order.side) {
+                rested = Err(self.reject(remaining_order, OrderError::BookFull));
+            } else {
+                rested = Ok(remaining_order.quantity);
+                self.add_order(remaining_order);
+            }
+        }
+
+        // Add trades to the orderbook
+        self.record(&trades);
+        
+        (trades, rested)
+    }
+
+    /// How much of `order` the opposite side could fill within its limit
+    /// price and slippage guard, hidden orders included. Self-trade
+    /// prevention is not taken into account.
+    fn matchable_quantity(&self, order: &Order) -> Quantity {
+        let levels: Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)>> = match order.side {
+            Side::Buy => Box::new(self.sell_orders.iter()),
+            Side::Sell => Box::new(self.buy_orders.iter().rev()),
+        };
+
+        let mut first_price = None;
+        let mut quantity = Quantity::ZERO;
+        for (&price, orders) in levels {
+            let reference_price = *first_price.get_or_insert(price);
+            let within_slippage = order
+                .max_slippage_bps
+                .is_none_or(|bps| !exceeds_slippage(order.side, reference_price, price, bps));
+            if !order.can_trade_at(price, self.cross_rule) || !within_slippage {
+                break;
+            }
+            quantity += orders
+                .iter()
+                .filter(|resting| !resting.all_or_none || resting.quantity <= order.quantity)
+                .map(|resting| resting.quantity)
+                .sum::<Quantity>();
+        }
+
+        quantity
+    }
+
+    fn is_side_full(&self, side: Side) -> bool {
+        let Some(max) = self.max_orders_per_side else {
+            return false;
+        };
+
+        self.levels(side).values().map(VecDeque::len).sum::<usize>() >= max
+    }
+
+    /// The resting orders on `side`, keyed by price.
+    fn levels(&self, side: Side) -> &BTreeMap<u64, VecDeque<Order>> {
+        match side {
+            Side::Buy => &self.buy_orders,
+            Side::Sell => &self.sell_orders,
+        }
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<u64, VecDeque<Order>> {
+        match side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        }
+    }
+
+    /// Removes a resting order from the book and returns it.
+    ///
+    /// Pending stop orders can be cancelled the same way. Pegged orders are
+    /// repriced afterwards, since the cancel may have moved the touch.
+    pub fn cancel_order(&mut self, order_id: &str) -> Result<Order, OrderError> {
+        let order = self.remove_order(order_id)?;
+        self.log_amendment(&order, AmendmentKind::Cancel, order.price, Quantity::ZERO, None);
+        self.reprice_pegs();
+        Ok(order)
+    }
+
+    /// Returns the `n` resting orders with the earliest timestamps, oldest
+    /// first. Orders created at the same instant keep their arrival order.
+    pub fn oldest_orders(&self, n: usize) -> Vec<&Order> {
+        let mut orders: Vec<&Order> = self.orders_by_id.values().collect();
+        orders.sort_by_key(|order| (order.timestamp, order.seq));
+        orders.truncate(n);
+        orders
+    }
+
+    /// Finds a resting or pending stop order of `trader_id` by its client
+    /// order id.
+    pub fn find_by_client_id(&self, trader_id: &str, client_id: &str) -> Option<&Order> {
+        self.orders_by_id
+            .values()
+            .chain(&self.pending_stops)
+            .find(|order| order.trader_id == trader_id && order.client_order_id.as_deref() == Some(client_id))
+    }
+
+    /// Returns the cancels, reductions and replacements made to an order,
+    /// oldest first.
+    pub fn amendment_history(&self, order_id: &str) -> Vec<&Amendment> {
+        self.amendments.iter().filter(|amendment| amendment.order_id == order_id).collect()
+    }
+
+    fn log_amendment(
+        &mut self,
+        order: &Order,
+        kind: AmendmentKind,
+        new_price: u64,
+        new_quantity: Quantity,
+        replacement_id: Option<String>,
+    ) {
+        self.amendments.push(Amendment {
+            order_id: order.id.clone(),
+            kind,
+            old_price: order.price,
+            old_quantity: order.quantity,
+            new_price,
+            new_quantity,
+            replacement_id,
+            timestamp: (self.clock)(),
+        });
+    }
+
+    /// Reduces a resting order's quantity by `by` while keeping its place in
+    /// the queue. Reducing by its whole quantity or more cancels it.
+    pub fn reduce_order(&mut self, order_id: &str, by: Quantity) -> Result<(), OrderError> {
+        let order = self.orders_by_id.get_mut(order_id).ok_or(OrderError::OrderNotFound)?;
+
+        if by >= order.quantity {
+            return self.cancel_order(order_id).map(|_| ());
+        }
+
+        let before = order.clone();
+        order.quantity -= by;
+        let (side, price, quantity) = (order.side, order.price, order.quantity);
+        self.log_amendment(&before, AmendmentKind::Reduce, price, quantity, None);
+
+        if let Some(resting) = self
+            .levels_mut(side)
+            .get_mut(&price)
+            .and_then(|orders| orders.iter_mut().find(|resting| resting.id == order_id))
+        {
+            resting.quantity = quantity;
+        }
+
+        Ok(())
+    }
+
+    /// Drops empty price levels, releases spare capacity and checks the id
+    /// index against the resting orders, repairing any entry that disagrees.
+    /// Nothing needs cleaning in a healthy book; this is maintenance for
+    /// long-running ones.
+    pub fn compact(&mut self) -> CompactionReport {
+        let mut report = CompactionReport::default();
+
+        for levels in [&mut self.buy_orders, &mut self.sell_orders] {
+            let before = levels.len();
+            levels.retain(|_, orders| !orders.is_empty());
+            report.empty_levels_removed += before - levels.len();
+
+            for orders in levels.values_mut() {
+                orders.shrink_to_fit();
+            }
+        }
+
+        let resting: HashMap<&str, &Order> = self
+            .buy_orders
+            .values()
+            .chain(self.sell_orders.values())
+            .flatten()
+            .map(|order| (order.id.as_str(), order))
+            .collect();
+
+        let before = self.orders_by_id.len();
+        self.orders_by_id.retain(|order_id, _| resting.contains_key(order_id.as_str()));
+        report.stale_ids_removed = before - self.orders_by_id.len();
+
+        for (order_id, order) in resting {
+            if self.orders_by_id.get(order_id) != Some(order) {
+                self.orders_by_id.insert(order_id.to_string(), order.clone());
+                report.ids_repaired += 1;
+            }
+        }
+
+        self.orders_by_id.shrink_to_fit();
+        let orders_by_id = &self.orders_by_id;
+        self.pegged_ids.retain(|order_id| orders_by_id.contains_key(order_id));
+        self.pegged_ids.shrink_to_fit();
+
+        report
+    }
+
+    /// Moves a resting order to a better price (higher for a buy, lower for a
+    /// sell) at the front of the new level, ahead of the orders already
+    /// there from traders of the same or lower priority. It keeps its id.
+    ///
+    /// Fails with `OrderError::NotAnImprovement` if `new_price` is not
+    /// better, or with `OrderError::CrossedBook` if it would trade against
+    /// the other side; use `replace_order` to cross.
+    pub fn improve_order(&mut self, order_id: &str, new_price: u64) -> Result<(), OrderError> {
+        let order = self.orders_by_id.get(order_id).ok_or(OrderError::OrderNotFound)?;
+        let side = order.side;
+
+        let improves = match side {
+            Side::Buy => new_price > order.price,
+            Side::Sell => new_price < order.price,
+        };
+        if !improves {
+            return Err(OrderError::NotAnImprovement);
+        }
+
+        // Hidden orders would trade too, so they count here
+        if self.top_price(side.opposite()).is_some_and(|price| crosses(side, new_price, price, self.cross_rule)) {
+            return Err(OrderError::CrossedBook);
+        }
+
+        let before = order.clone();
+        let levels = match side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        };
+
+        let orders = levels.get_mut(&before.price).ok_or(OrderError::OrderNotFound)?;
+        let index = orders.iter().position(|resting| resting.id == order_id).ok_or(OrderError::OrderNotFound)?;
+        let mut moved = orders.remove(index).ok_or(OrderError::OrderNotFound)?;
+        if orders.is_empty() {
+            levels.remove(&before.price);
+        }
+
+        // A later jump ranks ahead of an earlier one
+        moved.price = new_price;
+        moved.queue_jump = self.next_queue_jump;
+        self.next_queue_jump += 1;
+        if let Some(indexed) = self.orders_by_id.get_mut(order_id) {
+            indexed.price = new_price;
+            indexed.queue_jump = moved.queue_jump;
+        }
+        insert_by_priority(levels.entry(new_price).or_default(), moved, &self.trader_priority);
+        self.log_amendment(&before, AmendmentKind::Improve, new_price, before.quantity, None);
+        self.reprice_pegs();
+
+        Ok(())
+    }
+
+    /// Cancels every resting buy priced below `threshold`, or every resting
+    /// sell priced above it, and returns them from the best price outward.
+    /// Only the affected levels are visited.
+    pub fn cancel_beyond(&mut self, side: Side, threshold: u64) -> Vec<Order> {
+        let removed = match side {
+            Side::Buy => {
+                let kept = self.buy_orders.split_off(&threshold);
+                std::mem::replace(&mut self.buy_orders, kept)
+            }
+            Side::Sell => match threshold.checked_add(1) {
+                Some(first_above) => self.sell_orders.split_off(&first_above),
+                None => BTreeMap::new(),
+            },
+        };
+
+        let cancelled: Vec<Order> = match side {
+            Side::Buy => removed.into_values().rev().flatten().collect(),
+            Side::Sell => removed.into_values().flatten().collect(),
+        };
+        for order in &cancelled {
+            self.orders_by_id.remove(&order.id);
+            self.log_amendment(order, AmendmentKind::Cancel, order.price, Quantity::ZERO, None);
+        }
+
+        self.reprice_pegs();
+        cancelled
+    }
+
+    /// Cancels and returns the highest-priority resting order at `price` on
+    /// `side`, i.e. the one that would match next.
+    pub fn cancel_front(&mut self, side: Side, price: u64) -> Option<Order> {
+        let orders = self.levels(side).get(&price)?;
+        let order_id = orders[next_to_match(orders, |_| true)?].id.clone();
+
+        self.cancel_order(&order_id).ok()
+    }
+
+    /// Takes an order out of the book, or out of the pending stops, without
+    /// any follow-up processing.
+    fn remove_order(&mut self, order_id: &str) -> Result<Order, OrderError> {
+        if let Some(index) = self.pending_stops.iter().position(|stop| stop.id == order_id) {
+            return Ok(self.pending_stops.remove(index));
+        }
+
+        let order = self.orders_by_id.remove(order_id).ok_or(OrderError::OrderNotFound)?;
+
+        let levels = match order.side {
+            Side::Buy => &mut self.buy_orders,
+            Side::Sell => &mut self.sell_orders,
+        };
+
+        if let Some(orders) = levels.get_mut(&order.price) {
+            orders.retain(|resting| resting.id != order.id);
+            
+            // If no orders left at this price, remove the price level
+            if orders.is_empty() {
+                levels.remove(&order.price);
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Cancels every good-till-date order whose expiry is at or before `now`
+    /// and returns them, earliest expiry first.
+    ///
+    /// Expiries are kept in a min-heap, so the sweep only touches orders that
+    /// have actually expired. Heap entries for orders that were filled or
+    /// cancelled in the meantime are discarded as they are popped.
+    pub fn expire_orders(&mut self, now: DateTime<Utc>) -> Vec<Order> {
+        let mut expired = Vec::new();
+
+        while let Some((Reverse(expires_at), _)) = self.expiries.peek() {
+            if *expires_at > now {
+                break;
+            }
+
+            if let Some((_, order_id)) = self.expiries.pop() {
+                if let Ok(order) = self.cancel_order(&order_id) {
+                    expired.push(order);
+                }
+            }
+        }
+
+        expired
+    }
+
+    /// Cancels every resting order from `trader_id` on both sides of the book
+    /// and returns them, bids first. Pegged orders are repriced afterwards.
+    pub fn cancel_all_for_trader(&mut self, trader_id: &str) -> Vec<Order> {
+        let mut cancelled = Vec::new();
+
+        for levels in [&mut self.buy_orders, &mut self.sell_orders] {
+            for orders in levels.values_mut() {
+                let (theirs, others): (VecDeque<Order>, VecDeque<Order>) = std::mem::take(orders)
+                    .into_iter()
+                    .partition(|order| order.trader_id == trader_id);
+                *orders = others;
+                cancelled.extend(theirs);
+            }
+            
+            // Remove any price levels left empty
+            levels.retain(|_, orders| !orders.is_empty());
+        }
+
+        for order in &cancelled {
+            self.orders_by_id.remove(&order.id);
+            self.log_amendment(order, AmendmentKind::Cancel, order.price, Quantity::ZERO, None);
+        }
+
+        self.reprice_pegs();
+        cancelled
+    }
+
+    /// Moves a resting order to `new_price`, keeping its quantity. See
+    /// `amend_order` for when it keeps its time priority.
+    pub fn replace_order(&mut self, order_id: &str, new_price: u64) -> Result<Vec<Trade>, OrderError> {
+        let quantity = self.orders_by_id.get(order_id).ok_or(OrderError::OrderNotFound)?.quantity;
+        self.amend_order(order_id, new_price, quantity)
+    }
+
+    /// Changes a resting order's price and quantity.
+    ///
+    /// Keeping the price and not increasing the quantity amends the order in
+    /// place: it keeps its id and its place in the queue. Any other change
+    /// cancels it and resubmits it with the new price and quantity and all
+    /// its other terms (hidden, expiry, pegging and so on). The replacement
+    /// gets a new id and loses its time priority. It goes through normal
+    /// matching, so the returned trades show whether the new price crossed the
+    /// book.
+    ///
+    /// If the book rejects the replacement without it trading, the original
+    /// order is put back where it was and the rejection is returned. A
+    /// replacement that traded before its remainder was rejected is not
+    /// undone; its trades stay in the history and the rejection is returned.
+    pub fn amend_order(&mut self, order_id: &str, new_price: u64, new_quantity: Quantity) -> Result<Vec<Trade>, OrderError> {
+        let order = self.orders_by_id.get(order_id).ok_or(OrderError::OrderNotFound)?;
+        if new_quantity.is_zero() {
+            return Err(OrderError::ZeroQuantity);
+        }
+
+        if new_price == order.price && new_quantity <= order.quantity {
+            let by = order.quantity - new_quantity;
+            if !by.is_zero() {
+                self.reduce_order(order_id, by)?;
+            }
+            return Ok(Vec::new());
+        }
+
+        let cancelled = self.remove_order(order_id)?;
+
+        let replacement = Order {
+            id: Uuid::new_v4().to_string(),
+            price: new_price,
+            quantity: new_quantity,
+            timestamp: (self.clock)(),
+            ..cancelled.clone()
+        };
+
+        let outcome = self.submit(replacement);
+        if let OrderStatus::Rejected(error) = outcome.status {
+            if outcome.trades.is_empty() {
+                self.add_order(cancelled);
+                self.reprice_pegs();
+            } else {
+                self.log_amendment(&cancelled, AmendmentKind::Replace, new_price, new_quantity, Some(outcome.order_id));
+            }
+            return Err(error);
+        }
+
+        self.log_amendment(&cancelled, AmendmentKind::Replace, new_price, new_quantity, Some(outcome.order_id));
+        Ok(outcome.trades)
+    }
+
+    /// Moves every resting order of `trader_id` by `bid_delta` (buys) or
+    /// `ask_delta` (sells) through `replace_order`, oldest first, and returns
+    /// the trades the new prices produced. New prices are clamped to
+    /// `1..=u64::MAX`. Pegged orders keep tracking their reference instead.
+    pub fn reprice_trader_orders(&mut self, trader_id: &str, bid_delta: i64, ask_delta: i64) -> Vec<Trade> {
+        let mut moves: Vec<(u64, String, u64)> = self
+            .orders_by_id
+            .values()
+            .filter(|order| order.trader_id == trader_id && order.peg.is_none())
+            .filter_map(|order| {
+                let delta = match order.side {
+                    Side::Buy => bid_delta,
+                    Side::Sell => ask_delta,
+                };
+                let price = order.price.saturating_add_signed(delta).max(1);
+                (price != order.price).then(|| (order.seq, order.id.clone(), price))
+            })
+            .collect();
+        moves.sort();
+
+        let mut trades = Vec::new();
+        for (_, order_id, price) in moves {
+            // An earlier move may already have filled this order
+            if let Ok(new_trades) = self.replace_order(&order_id, price) {
+                trades.extend(new_trades);
+            }
+        }
+
+        trades
+    }
+
+    fn add_order(&mut self, order: Order) {
+        if let Some(expires_at) = order.expires_at {
+            self.expiries.push((Reverse(expires_at), order.id.clone()));
+        }
+        if order.peg.is_some() {
+            self.pegged_ids.insert(order.id.clone());
+        }
+
+        match order.side {
+            Side::Buy => self.add_buy_order(order),
+            Side::Sell => self.add_sell_order(order),
+        }
+    }
+
+    fn add_buy_order(&mut self, order: Order) {
+        let price = order.price;
+        self.orders_by_id.insert(order.id.clone(), order.clone());
+        
+        insert_by_priority(self.buy_orders.entry(price).or_default(), order, &self.trader_priority);
+    }
+
+    fn add_sell_order(&mut self, order: Order) {
+        let price = order.price;
+        self.orders_by_id.insert(order.id.clone(), order.clone());
+        
+        insert_by_priority(self.sell_orders.entry(price).or_default(), order, &self.trader_priority);
+    }
+
+    /// The best displayed bid price. Hidden orders are left out.
+    pub fn get_best_bid(&self) -> Option<u64> {
+        self.bid_levels().next().map(|(price, _)| price)
+    }
+
+    /// The best displayed ask price. Hidden orders are left out.
+    pub fn get_best_ask(&self) -> Option<u64> {
+        self.ask_levels().next().map(|(price, _)| price)
+    }
+
+    /// Best price resting on `side`, hidden orders included.
+    fn top_price(&self, side: Side) -> Option<u64> {
+        match side {
+            Side::Buy => self.buy_orders.keys().next_back().copied(),
+            Side::Sell => self.sell_orders.keys().next().copied(),
+        }
+    }
+
+    /// The resting buy orders by price level, in queue order within each
+    /// level. Keys ascend, so the best bid is the last key.
+    pub fn buy_levels(&self) -> &BTreeMap<u64, VecDeque<Order>> {
+        &self.buy_orders
+    }
+
+    /// The resting sell orders by price level, in queue order within each
+    /// level. Keys ascend, so the best ask is the first key.
+    pub fn sell_levels(&self) -> &BTreeMap<u64, VecDeque<Order>> {
+        &self.sell_orders
+    }
+
+    /// Classifies the book by its best displayed bid and best displayed ask.
+    pub fn book_state(&self) -> BookState {
+        match (self.get_best_bid(), self.get_best_ask()) {
+            (None, None) => BookState::Empty,
+            (Some(_), None) | (None, Some(_)) => BookState::OneSided,
+            (Some(best_bid), Some(best_ask)) if best_bid < best_ask => BookState::Normal,
+            (Some(best_bid), Some(best_ask)) if best_bid == best_ask => BookState::Locked,
+            (Some(_), Some(_)) => BookState::Crossed,
+        }
+    }
+
+    /// Returns the best displayed bid price and the total displayed quantity
+    /// resting at it. Hidden orders are left out.
+    pub fn best_bid_with_qty(&self) -> Option<(u64, Quantity)> {
+        self.bid_levels().next()
+    }
+
+    /// Returns the best displayed ask price and the total displayed quantity
+    /// resting at it. Hidden orders are left out.
+    pub fn best_ask_with_qty(&self) -> Option<(u64, Quantity)> {
+        self.ask_levels().next()
+    }
+
+    /// Returns every order rejected by `submit`, with the reason, oldest first.
+    ///
+    /// When only the remainder of an order was refused (e.g. the book was
+    /// full after it had partly matched), the logged order carries the
+    /// refused remaining quantity.
+    pub fn rejected_orders(&self) -> &[(Order, OrderError)] {
+        &self.rejected
+    }
+
+    /// Takes the recorded trade history out of the book, leaving it empty.
+    pub fn drain_trades(&mut self) -> Vec<Trade> {
+        self.fills_by_order.clear();
+        std::mem::take(&mut self.trades)
+    }
+
+    /// Returns the recorded fills of one order, oldest first.
+    pub fn fills_for_order(&self, order_id: &str) -> Vec<Trade> {
+        self.fills_by_order
+            .get(order_id)
+            .map(|indices| indices.iter().map(|&index| self.trades[index].clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns how far the average fill price of an order was from
+    /// `reference_price`, in basis points, rounded with the book's
+    /// `RoundingMode`. Positive means worse for the order: it bought higher
+    /// or sold lower. `None` if the order has no recorded fills.
+    pub fn slippage_bps(&self, order_id: &str, reference_price: u64) -> Option<i64> {
+        let fills = self.fills_for_order(order_id);
+        let side = if fills.first()?.buy_order_id == order_id { Side::Buy } else { Side::Sell };
+        if reference_price == 0 {
+            return None;
+        }
+
+        let quantity: u128 = fills.iter().map(|trade| trade.quantity.raw() as u128).sum();
+        let notional: u128 = fills.iter().map(|trade| trade.price as u128 * trade.quantity.raw() as u128).sum();
+        let reference_notional = reference_price as u128 * quantity;
+
+        // (average - reference) / reference, kept in integers
+        let (worse, difference) = match side {
+            Side::Buy => (notional >= reference_notional, notional.abs_diff(reference_notional)),
+            Side::Sell => (notional <= reference_notional, notional.abs_diff(reference_notional)),
+        };
+        let bps = self.rounding_mode.divide(difference * 10_000, reference_notional);
+        let bps = i64::try_from(bps).unwrap_or(i64::MAX);
+
+        Some(if worse { bps } else { -bps })
+    }
+
+    /// Busts a recorded trade: removes it from the trade history and backs it
+    /// out of `total_volume`, `trade_count`, the last trade price and the
+    /// traders' positions. The book's resting orders are left as they are.
+    pub fn bust_trade(&mut self, trade_id: &str) -> Result<Trade, OrderError> {
+        let index = self
+            .trades
+            .iter()
+            .position(|trade| trade.id == trade_id)
+            .ok_or(OrderError::TradeNotFound)?;
+
+        let trade = self.trades.remove(index);
+        self.total_volume -= trade.quantity;
+        self.trade_count -= 1;
+        self.last_trade_price = self.trades.last().map(|trade| trade.price);
+        shift_positions(&mut self.positions, &trade, -1);
+
+        // Later trades have shifted down, so rebuild the fill index
+        self.fills_by_order.clear();
+        for (index, trade) in self.trades.iter().enumerate() {
+            self.fills_by_order.entry(trade.buy_order_id.clone()).or_default().push(index);
+            self.fills_by_order.entry(trade.sell_order_id.clone()).or_default().push(index);
+        }
+
+        Ok(trade)
+    }
+
+    /// Appends trades to the history, if recording is on, and indexes them
+    /// by both order ids.
+    fn record(&mut self, trades: &[Trade]) {
+        if !self.record_trades {
+            return;
+        }
+
+        for trade in trades {
+            let index = self.trades.len();
+            self.fills_by_order.entry(trade.buy_order_id.clone()).or_default().push(index);
+            self.fills_by_order.entry(trade.sell_order_id.clone()).or_default().push(index);
+            let copy = pooled_copy(&mut self.trade_pool, trade);
+            self.trades.push(copy);
+        }
+    }
+
+    /// Exports the recorded trades as CSV, one row per trade after a header
+    /// row. Timestamps are RFC 3339.
+    pub fn trades_to_csv(&self) -> String {
+        let mut out = String::from("id,symbol,price,quantity,buy_order_id,sell_order_id,timestamp\n");
+
+        for trade in &self.trades {
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{},{}",
+                csv_field(&trade.id),
+                csv_field(&trade.symbol),
+                trade.price,
+                trade.quantity,
+                csv_field(&trade.buy_order_id),
+                csv_field(&trade.sell_order_id),
+                trade.timestamp.to_rfc3339(),
+            );
+        }
+
+        out
+    }
+
+    /// The net quantity `trader_id` has bought (`Side::Buy`) or sold
+    /// (`Side::Sell`) in this book, or `None` if they are flat.
+    pub fn position(&self, trader_id: &str) -> Option<(Side, Quantity)> {
+        let net = self.positions.get(trader_id).copied().unwrap_or(0);
+        let size = Quantity::from_raw(u64::try_from(net.unsigned_abs()).unwrap_or(u64::MAX));
+        match net {
+            0 => None,
+            net if net > 0 => Some((Side::Buy, size)),
+            _ => Some((Side::Sell, size)),
+        }
+    }
+
+    /// Total quantity traded in this book since it was created.
+    pub fn total_volume(&self) -> Quantity {
+        self.total_volume
+    }
+
+    /// Number of trades executed in this book since it was created.
+    pub fn trade_count(&self) -> usize {
+        self.trade_count
+    }
+
+    /// Returns every trade in which `trader_id` was the buyer or the seller.
+    pub fn trades_for(&self, trader_id: &str) -> Vec<&Trade> {
+        self.trades
+            .iter()
+            .filter(|trade| trade.buy_trader_id == trader_id || trade.sell_trader_id == trader_id)
+            .collect()
+    }
+
+    /// Returns the trades executed between `start` and `end`, both inclusive.
+    pub fn trades_between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<&Trade> {
+        self.trades
+            .iter()
+            .filter(|trade| trade.timestamp >= start && trade.timestamp <= end)
+            .collect()
+    }
+
+    /// Total quantity of the last `n` recorded trades.
+    pub fn recent_volume(&self, n: usize) -> Quantity {
+        self.recent_trades(n).iter().map(|trade| trade.quantity).sum()
+    }
+
+    /// Prices of the last `n` recorded trades, newest last.
+    pub fn recent_trade_prices(&self, n: usize) -> Vec<u64> {
+        self.recent_trades(n).iter().map(|trade| trade.price).collect()
+    }
+
+    fn recent_trades(&self, n: usize) -> &[Trade] {
+        &self.trades[self.trades.len().saturating_sub(n)..]
+    }
+
+    /// Sums the recorded trades per `(buy_trader_id, sell_trader_id)` pair
+    /// into `(quantity, notional)`. Each pair's notional is rounded to a
+    /// whole price unit with the book's `RoundingMode`.
+    pub fn settlement_batches(&self) -> HashMap<(String, String), (Quantity, u128)> {
+        let mut batches: HashMap<(String, String), (Quantity, u128)> = HashMap::new();
+
+        for trade in &self.trades {
+            let batch = batches
+                .entry((trade.buy_trader_id.clone(), trade.sell_trader_id.clone()))
+                .or_default();
+            batch.0 += trade.quantity;
+            batch.1 += trade.price as u128 * trade.quantity.raw() as u128;
+        }
+
+        for batch in batches.values_mut() {
+            batch.1 = self.rounding_mode.divide(batch.1, Quantity::UNIT as u128);
+        }
+        batches
+    }
+
+    /// Returns the volume-weighted average price of the recorded trades,
+    /// rounded to an integer price with the book's `RoundingMode`.
+    pub fn vwap(&self) -> Option<u64> {
+        let volume: u128 = self.trades.iter().map(|trade| trade.quantity.raw() as u128).sum();
+        if volume == 0 {
+            return None;
+        }
+
+        let notional: u128 = self.trades.iter().map(|trade| trade.price as u128 * trade.quantity.raw() as u128).sum();
+        Some(self.rounding_mode.divide(notional, volume) as u64)
+    }
+
+    /// Returns the time-weighted average trade price between `start` and
+    /// `end`, both inclusive. Each trade's price counts for the time until
+    /// the next trade, the last one's until `end`. If that leaves no time at
+    /// all, the prices are averaged evenly.
+    pub fn twap(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Option<f64> {
+        let mut trades = self.trades_between(start, end);
+        if trades.is_empty() {
+            return None;
+        }
+        trades.sort_by_key(|trade| trade.timestamp);
+
+        let ends = trades.iter().skip(1).map(|trade| trade.timestamp).chain([end]);
+        let (weighted, total_weight) = trades.iter().zip(ends).fold((0.0, 0.0), |(weighted, total), (trade, until)| {
+            let weight = (until - trade.timestamp).num_nanoseconds().unwrap_or(i64::MAX) as f64;
+            (weighted + trade.price as f64 * weight, total + weight)
+        });
+
+        if total_weight == 0.0 {
+            let sum: f64 = trades.iter().map(|trade| trade.price as f64).sum();
+            return Some(sum / trades.len() as f64);
+        }
+        Some(weighted / total_weight)
+    }
+
+    /// Returns the fee charged on `trade` at the book's fee rate, rounded to
+    /// a whole price unit with the book's `RoundingMode`.
+    pub fn trade_fee(&self, trade: &Trade) -> u64 {
+        let notional = trade.price as u128 * trade.quantity.raw() as u128;
+        let fee = self.rounding_mode.divide(notional * self.fee_bps as u128, 10_000 * Quantity::UNIT as u128);
+        u64::try_from(fee).unwrap_or(u64::MAX)
+    }
+
+    /// Returns the sum of `trade_fee` over the recorded trades. Each trade's
+    /// fee is rounded on its own before summing.
+    pub fn total_fees(&self) -> u64 {
+        self.trades.iter().map(|trade| self.trade_fee(trade)).sum()
+    }
+
+    /// Returns the total `price * quantity` of the displayed orders resting
+    /// on `side`, rounded to a whole price unit with the book's
+    /// `RoundingMode`.
+    pub fn notional(&self, side: Side) -> u128 {
+        let notional = self
+            .levels_from_touch(side)
+            .map(|(price, quantity)| price as u128 * quantity.raw() as u128)
+            .sum();
+        self.rounding_mode.divide(notional, Quantity::UNIT as u128)
+    }
+
+    /// Returns the resting notional of both sides together.
+    pub fn total_notional(&self) -> u128 {
+        self.notional(Side::Buy) + self.notional(Side::Sell)
+    }
+
+    /// Returns the average price a `side` order would pay to trade `notional`
+    /// worth of the displayed opposite book, or `None` if the book is not
+    /// deep enough. The last level reached may be taken in part.
+    pub fn impact_price_for_notional(&self, side: Side, notional: u128) -> Option<f64> {
+        if notional == 0 {
+            return None;
+        }
+
+        let mut remaining = notional.saturating_mul(Quantity::UNIT as u128);
+        let mut quantity = 0.0;
+        for (price, level_qty) in self.levels_from_touch(side.opposite()) {
+            let level_notional = price as u128 * level_qty.raw() as u128;
+            if level_notional >= remaining {
+                quantity += remaining as f64 / Quantity::UNIT as f64 / price as f64;
+                return Some(notional as f64 / quantity);
+            }
+            remaining -= level_notional;
+            quantity += level_qty.to_f64();
+        }
+
+        None
+    }
+
+    /// Returns the quantity-weighted average price of the best `n` displayed
+    /// levels on `side`, or of every level if there are fewer.
+    pub fn avg_price_top_n(&self, side: Side, n: usize) -> Option<f64> {
+        let (notional, quantity) = self
+            .levels_from_touch(side)
+            .take(n)
+            .fold((0u128, 0u128), |(notional, quantity), (price, level_qty)| {
+                (notional + price as u128 * level_qty.raw() as u128, quantity + level_qty.raw() as u128)
+            });
+
+        (quantity > 0).then(|| notional as f64 / quantity as f64)
+    }
+
+    /// Returns the quantity-weighted average price of all displayed liquidity
+    /// on `side`, or `None` if there is none.
+    pub fn center_of_mass(&self, side: Side) -> Option<f64> {
+        self.avg_price_top_n(side, usize::MAX)
+    }
+
+    /// Returns the displayed levels of `side` from the touch outward, each
+    /// with the total quantity from the touch up to and including it.
+    pub fn cumulative_depth(&self, side: Side) -> Vec<(u64, Quantity)> {
+        self.levels_from_touch(side)
+            .scan(Quantity::ZERO, |total, (price, quantity)| {
+                *total += quantity;
+                Some((price, *total))
+            })
+            .collect()
+    }
+
+    /// Returns the size-weighted mid price (microprice) of the touch:
+    /// `(best_bid * ask_qty + best_ask * bid_qty) / (bid_qty + ask_qty)`.
+    pub fn weighted_mid(&self) -> Option<f64> {
+        let (best_bid, bid_qty) = self.best_bid_with_qty()?;
+        let (best_ask, ask_qty) = self.best_ask_with_qty()?;
+
+        let total_qty = bid_qty.to_f64() + ask_qty.to_f64();
+        if total_qty == 0.0 {
+            return None;
+        }
+
+        Some((best_bid as f64 * ask_qty.to_f64() + best_ask as f64 * bid_qty.to_f64()) / total_qty)
+    }
+
+    /// Returns the bid-ask spread relative to the mid price, in basis points,
+    /// rounded to a whole basis point with the book's `RoundingMode`.
+    pub fn spread_bps(&self) -> Option<u32> {
+        let best_bid = self.get_best_bid()? as u128;
+        let best_ask = self.get_best_ask()? as u128;
+
+        // (ask - bid) / ((ask + bid) / 2), kept in integers
+        let numerator = best_ask.saturating_sub(best_bid) * 20_000;
+        let denominator = best_ask + best_bid;
+        if denominator == 0 {
+            return None;
+        }
+
+        let bps = self.rounding_mode.divide(numerator, denominator);
+        Some(u32::try_from(bps).unwrap_or(u32::MAX))
+    }
+
+    /// Returns the quantity queued ahead of a resting order at its price
+    /// level, or `None` if the order is not resting.
+    pub fn queue_position(&self, order_id: &str) -> Option<Quantity> {
+        let order = self.orders_by_id.get(order_id)?;
+        let orders = self.levels(order.side).get(&order.price)?;
+        let index = orders.iter().position(|resting| resting.id == order_id)?;
+
+        // Hidden orders queue behind every displayed order at the level
+        let ahead = orders
+            .iter()
+            .enumerate()
+            .filter(|&(i, resting)| match (order.hidden, resting.hidden) {
+                (true, false) => true,
+                (false, true) => false,
+                _ => i < index,
+            })
+            .map(|(_, resting)| resting.quantity)
+            .sum();
+
+        Some(ahead)
+    }
+
+    /// Gathers the book's headline numbers in one call.
+    pub fn metrics(&self) -> BookMetrics {
+        let (bids, asks) = self.ladder();
+        let open_quantity = |levels: &[Level]| levels.iter().map(|&(_, quantity)| quantity).sum();
+        let (best_bid, best_ask) = (bids.first().map(|&(price, _)| price), asks.first().map(|&(price, _)| price));
+
+        BookMetrics {
+            symbol: self.symbol.clone(),
+            best_bid,
+            best_ask,
+            spread: best_bid.zip(best_ask).map(|(bid, ask)| ask.saturating_sub(bid)),
+            bid_levels: bids.len(),
+            ask_levels: asks.len(),
+            bid_quantity: open_quantity(&bids),
+            ask_quantity: open_quantity(&asks),
+            trade_count: self.trade_count,
+            total_volume: self.total_volume,
+            last_price: self.last_trade_price,
+        }
+    }
+
+    /// Captures the aggregated ladder of both sides.
+    pub fn snapshot(&self) -> BookSnapshot {
+        BookSnapshot {
+            symbol: self.symbol.clone(),
+            bids: self.bid_levels().collect(),
+            asks: self.ask_levels().collect(),
+        }
+    }
+
+    /// Stores a `snapshot` of the book, stamped with the book's clock.
+    pub fn record_snapshot(&mut self) {
+        let snapshot = self.snapshot();
+        self.snapshots.push(((self.clock)(), snapshot));
+    }
+
+    /// The snapshots stored by `record_snapshot`, oldest first.
+    pub fn snapshots(&self) -> &[(DateTime<Utc>, BookSnapshot)] {
+        &self.snapshots
+    }
+
+    /// Exports the recorded snapshots as a JSON array of
+    /// `{"timestamp", "symbol", "bids", "asks"}` objects, with each side a
+    /// list of `[price, quantity]` pairs from the touch outward. Timestamps
+    /// are RFC 3339.
+    pub fn snapshots_to_json(&self) -> String {
+        let levels_json = |levels: &[Level]| {
+            let pairs: Vec<String> = levels.iter().map(|(price, quantity)| format!("[{},{}]", price, quantity)).collect();
+            format!("[{}]", pairs.join(","))
+        };
+
+        let objects: Vec<String> = self
+            .snapshots
+            .iter()
+            .map(|(timestamp, snapshot)| {
+                format!(
+                    "{{\"timestamp\":{},\"symbol\":{},\"bids\":{},\"asks\":{}}}",
+                    json_string(&timestamp.to_rfc3339()),
+                    json_string(&snapshot.symbol),
+                    levels_json(&snapshot.bids),
+                    levels_json(&snapshot.asks),
+                )
+            })
+            .collect();
+
+        format!("[{}]", objects.join(","))
+    }
+
+    /// Estimates how much quantity must trade before a resting order starts to
+    /// fill: everything resting on its side at better prices, plus its
+    /// `queue_position` at its own level. Hidden orders are included since
+    /// they also match first. Returns `None` if the order is not resting.
+    pub fn estimated_fills_ahead(&self, order_id: &str) -> Option<Quantity> {
+        let order = self.orders_by_id.get(order_id)?;
+        let queue_ahead = self.queue_position(order_id)?;
+
+        let levels = self.levels(order.side);
+        let better_levels = match order.side {
+            Side::Buy => levels.range((Bound::Excluded(order.price), Bound::Unbounded)),
+            Side::Sell => levels.range(..order.price),
+        };
+        let better: Quantity = better_levels
+            .flat_map(|(_, orders)| orders)
+            .map(|resting| resting.quantity)
+            .sum();
+
+        Some(better + queue_ahead)
+    }
+
+    /// Estimates how long until a resting order is completely filled, from
+    /// `estimated_fills_ahead` plus its own quantity and the rate at which
+    /// the recorded trades have traded volume.
+    ///
+    /// Assumes that rate holds constant, that all of it trades against the
+    /// order's side, and that nothing joins the queue ahead of the order.
+    /// Returns `None` if the order is not resting or the recorded trades
+    /// span no time.
+    pub fn estimated_time_to_fill(&self, order_id: &str) -> Option<Duration> {
+        let to_fill = (self.estimated_fills_ahead(order_id)? + self.orders_by_id.get(order_id)?.quantity).raw() as u128;
+
+        let first = self.trades.iter().map(|trade| trade.timestamp).min()?;
+        let last = self.trades.iter().map(|trade| trade.timestamp).max()?;
+        let span_nanos = u128::try_from((last - first).num_nanoseconds()?).ok().filter(|&nanos| nanos > 0)?;
+        let volume: u128 = self.trades.iter().map(|trade| trade.quantity.raw() as u128).sum();
+
+        let nanos = to_fill * span_nanos / volume;
+        Some(Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX)))
+    }
+
+    /// Returns every level of the book as `(price, total_quantity)` pairs:
+    /// bids from the highest price down, asks from the lowest price up.
+    pub fn ladder(&self) -> (Vec<Level>, Vec<Level>) {
+        (self.bid_levels().collect(), self.ask_levels().collect())
+    }
+
+    /// Returns true when both books quote the same symbol with the same
+    /// quantity at every price on both sides. Order ids, timestamps, queue
+    /// order and trade history are ignored. Unlike `ladder`, hidden orders
+    /// are counted, since they trade just like displayed ones.
+    pub fn economically_equal(&self, other: &OrderBook) -> bool {
+        fn depth(levels: &BTreeMap<u64, VecDeque<Order>>) -> impl Iterator<Item = Level> + '_ {
+            levels
+                .iter()
+                .map(|(&price, orders)| (price, orders.iter().map(|order| order.quantity).sum::<Quantity>()))
+                .filter(|&(_, quantity)| !quantity.is_zero())
+        }
+
+        self.symbol == other.symbol
+            && depth(&self.buy_orders).eq(depth(&other.buy_orders))
+            && depth(&self.sell_orders).eq(depth(&other.sell_orders))
+    }
+
+    /// Returns the aggregated levels of `side` priced within `[low, high]`,
+    /// best price first. Only the levels in the range are visited.
+    pub fn orders_in_range(&self, side: Side, low: u64, high: u64) -> Vec<Level> {
+        if low > high {
+            return Vec::new();
+        }
+
+        let levels = self
+            .levels(side)
+            .range(low..=high)
+            .map(|(&price, orders)| (price, level_quantity(orders)))
+            .filter(|&(_, quantity)| !quantity.is_zero());
+
+        match side {
+            Side::Buy => levels.rev().collect(),
+            Side::Sell => levels.collect(),
+        }
+    }
+
+    /// Returns up to `n` `(trader_id, price, quantity)` rows for `side`, best
+    /// price first, with each trader's displayed orders at a price summed
+    /// into one row. Within a price, traders are listed in queue order of
+    /// their first order there.
+    pub fn top_trader_quotes(&self, side: Side, n: usize) -> Vec<(String, u64, Quantity)> {
+        let levels: Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)>> = match side {
+            Side::Buy => Box::new(self.buy_orders.iter().rev()),
+            Side::Sell => Box::new(self.sell_orders.iter()),
+        };
+
+        let mut quotes = Vec::new();
+        for (&price, orders) in levels {
+            let mut level_quotes: Vec<(String, u64, Quantity)> = Vec::new();
+            for order in orders.iter().filter(|order| !order.hidden) {
+                match level_quotes.iter_mut().find(|(trader_id, _, _)| *trader_id == order.trader_id) {
+                    Some((_, _, quantity)) => *quantity += order.quantity,
+                    None => level_quotes.push((order.trader_id.clone(), price, order.quantity)),
+                }
+            }
+
+            quotes.extend(level_quotes);
+            if quotes.len() >= n {
+                break;
+            }
+        }
+
+        quotes.truncate(n);
+        quotes
+    }
+
+    /// Buckets displayed resting volume into price bins of width `tick`,
+    /// mapping each bin's lower bound to `(bid_qty, ask_qty)`. Bins with no
+    /// volume are omitted. A `tick` of zero is treated as one.
+    pub fn volume_profile(&self, tick: u64) -> BTreeMap<u64, (Quantity, Quantity)> {
+        let tick = tick.max(1);
+        let mut profile: BTreeMap<u64, (Quantity, Quantity)> = BTreeMap::new();
+
+        for (price, quantity) in self.bid_levels() {
+            profile.entry(price - price % tick).or_default().0 += quantity;
+        }
+        for (price, quantity) in self.ask_levels() {
+            profile.entry(price - price % tick).or_default().1 += quantity;
+        }
+
+        profile
+    }
+
+    /// Returns the price and total quantity of the level with the most resting
+    /// quantity on `side`. Ties go to the level closest to the touch.
+    pub fn thickest_level(&self, side: Side) -> Option<Level> {
+        self.levels_from_touch(side).fold(None, |thickest, level| match thickest {
+            Some((_, quantity)) if quantity >= level.1 => thickest,
+            _ => Some(level),
+        })
+    }
+
+    /// Aggregated levels of `side`, best price first.
+    fn levels_from_touch(&self, side: Side) -> Box<dyn Iterator<Item = Level> + '_> {
+        match side {
+            Side::Buy => Box::new(self.bid_levels()),
+            Side::Sell => Box::new(self.ask_levels()),
+        }
+    }
+
+    /// Aggregated bid levels, best (highest) price first. Hidden orders are
+    /// left out, along with levels holding nothing else.
+    fn bid_levels(&self) -> impl Iterator<Item = Level> + '_ {
+        self.buy_orders
+            .iter()
+            .rev()
+            .map(|(&price, orders)| (price, level_quantity(orders)))
+            .filter(|&(_, quantity)| !quantity.is_zero())
+    }
+
+    /// Aggregated ask levels, best (lowest) price first. Hidden orders are
+    /// left out, along with levels holding nothing else.
+    fn ask_levels(&self) -> impl Iterator<Item = Level> + '_ {
+        self.sell_orders
+            .iter()
+            .map(|(&price, orders)| (price, level_quantity(orders)))
+            .filter(|&(_, quantity)| !quantity.is_zero())
+    }
+
+    /// Computes a CRC32 checksum over the top `levels` of the book.
+    ///
+    /// The checksummed string interleaves the bid and ask ladders from the
+    /// touch outwards as `bidprice:bidqty:askprice:askqty:...`, where each
+    /// quantity is the total resting at that price, written as `Quantity`
+    /// displays it (`20`, `2.5`). When one side has fewer
+    /// than `levels` levels its missing entries are simply omitted, so an empty
+    /// book checksums the empty string.
+    pub fn depth_checksum(&self, levels: usize) -> u32 {
+        let mut bids = self.bid_levels();
+        let mut asks = self.ask_levels();
+        let mut fields = Vec::new();
+
+        for _ in 0..levels {
+            if let Some((price, quantity)) = bids.next() {
+                fields.push(format!("{}:{}", price, quantity));
+            }
+            if let Some((price, quantity)) = asks.next() {
+                fields.push(format!("{}:{}", price, quantity));
+            }
+        }
+
+        crc32(fields.join(":").as_bytes())
+    }
+
+    pub fn display_order_book(&self) {
+        println!("{}", self.format_order_book());
+    }
+
+    /// Prints the top `levels` price levels of each side followed by the
+    /// spread. Deeper levels are summarized on one line per side.
+    pub fn display_order_book_n(&self, levels: usize) {
+        println!("{}", self.format_order_book_n(levels));
+    }
+
+    /// Renders the price ladder shown by `display_order_book` as a `String`.
+    pub fn format_order_book(&self) -> String {
+        let mut out = String::new();
+        
+        // Writing to a String cannot fail
+        let _ = writeln!(out, "Order Book for {}", self.symbol);
+        let _ = writeln!(out, "---------------------------");
+        
+        let _ = writeln!(out, "SELL ORDERS:");
+        let asks: Vec<Level> = self.ask_levels().collect();
+        for (price, total_quantity) in asks.iter().rev() {
+            let _ = writeln!(out, "  {}: {} shares", self.format_price(*price), total_quantity);
+        }
+        
+        let _ = writeln!(out, "---------------------------");
+        
+        let _ = writeln!(out, "BUY ORDERS:");
+        for (price, total_quantity) in self.bid_levels() {
+            let _ = writeln!(out, "  {}: {} shares", self.format_price(price), total_quantity);
+        }
+        
+        let _ = write!(out, "---------------------------");
+        
+        out
+    }
+
+    /// Renders every resting order, hidden ones included, level by level
+    /// from the touch outward, along with the best prices and trade count.
+    /// The best prices count hidden orders too, like the levels below them.
+    /// Order ids are cut to their first 8 characters. Meant for test failure
+    /// output; the text only depends on the book's state.
+    pub fn debug_dump(&self) -> String {
+        let price_or_none = |price: Option<u64>| price.map_or("none".to_string(), |price| price.to_string());
+
+        let mut out = String::new();
+        let _ = writeln!(out, "OrderBook {}", self.symbol);
+        let _ = writeln!(out, "  best bid: {}", price_or_none(self.top_price(Side::Buy)));
+        let _ = writeln!(out, "  best ask: {}", price_or_none(self.top_price(Side::Sell)));
+        let _ = writeln!(out, "  trades: {}", self.trade_count);
+
+        for (label, side) in [("asks", Side::Sell), ("bids", Side::Buy)] {
+            let _ = writeln!(out, "  {}:", label);
+            let levels: Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)>> = match side {
+                Side::Buy => Box::new(self.buy_orders.iter().rev()),
+                Side::Sell => Box::new(self.sell_orders.iter()),
+            };
+            for (price, orders) in levels {
+                let _ = writeln!(out, "    {}:", price);
+                for order in orders {
+                    let id: String = order.id.chars().take(8).collect();
+                    let hidden = if order.hidden { " hidden" } else { "" };
+                    let _ = writeln!(out, "      {} {} x{}{}", id, order.trader_id, order.quantity, hidden);
+                }
+            }
+        }
+
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    /// Renders the output of `display_order_book_n` as a `String`.
+    pub fn format_order_book_n(&self, levels: usize) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "Order Book for {}", self.symbol);
+        let _ = writeln!(out, "---------------------------");
+
+        let _ = writeln!(out, "SELL ORDERS:");
+        let asks: Vec<Level> = self.ask_levels().collect();
+        let (shown_asks, deeper_asks) = asks.split_at(levels.min(asks.len()));
+        write_deeper_levels(&mut out, deeper_asks);
+        for (price, total_quantity) in shown_asks.iter().rev() {
+            let _ = writeln!(out, "  {}: {} shares", self.format_price(*price), total_quantity);
+        }
+
+        let _ = writeln!(out, "---------------------------");
+
+        let _ = writeln!(out, "BUY ORDERS:");
+        let bids: Vec<Level> = self.bid_levels().collect();
+        let (shown_bids, deeper_bids) = bids.split_at(levels.min(bids.len()));
+        for (price, total_quantity) in shown_bids {
+            let _ = writeln!(out, "  {}: {} shares", self.format_price(*price), total_quantity);
+        }
+        write_deeper_levels(&mut out, deeper_bids);
+
+        let _ = writeln!(out, "---------------------------");
+        let _ = write!(out, "{}", self.spread_line());
+
+        out
+    }
+
+    /// Renders an integer price in major units, with the book's price scale
+    /// as the number of decimal places: `10000` is `100.00` at scale 2.
+    pub fn format_price(&self, price: u64) -> String {
+        if self.price_scale == 0 {
+            return price.to_string();
+        }
+
+        let unit = 10u128.pow(self.price_scale);
+        let price = price as u128;
+        format!("{}.{:0width$}", price / unit, price % unit, width = self.price_scale as usize)
+    }
+
+    /// The one-line spread summary printed after each simulation round.
+    fn spread_line(&self) -> String {
+        match (self.get_best_bid(), self.get_best_ask()) {
+            (Some(best_bid), Some(best_ask)) => format!(
+                "Current spread: ${} - ${} = ${}",
+                self.format_price(best_ask),
+                self.format_price(best_bid),
+                self.format_price(best_ask.saturating_sub(best_bid))
+            ),
+            (Some(best_bid), None) => format!("Best bid: ${} (no asks)", self.format_price(best_bid)),
+            (None, Some(best_ask)) => format!("Best ask: ${} (no bids)", self.format_price(best_ask)),
+            (None, None) => "Order book is empty".to_string(),
+        }
+    }
+}
+
+/// Writes the "levels beyond the display depth" summary line, if any.
+fn write_deeper_levels(out: &mut String, deeper: &[Level]) {
+    if deeper.is_empty() {
+        return;
+    }
+
+    let quantity: Quantity = deeper.iter().map(|&(_, quantity)| quantity).sum();
+    let _ = writeln!(out, "  ... {} more levels: {} shares", deeper.len(), quantity);
+}
+
+/// An `OrderBook` that can be shared between threads, e.g. behind an `Arc`.
+///
+/// Writers (order entry and cancels) take the lock exclusively; readers can
+/// query the book concurrently with each other.
+pub struct SyncOrderBook {
+    book: RwLock<OrderBook>,
+}
+
+impl SyncOrderBook {
+    pub fn new(symbol: String) -> Self {
+        SyncOrderBook::from_book(OrderBook::new(symbol))
+    }
+
+    pub fn from_book(book: OrderBook) -> Self {
+        SyncOrderBook {
+            book: RwLock::new(book),
+        }
+    }
+
+    pub fn place_order(&self, order: Order) -> Vec<Trade> {
+        self.write(|book| book.place_order(order))
+    }
+
+    pub fn submit(&self, order: Order) -> OrderOutcome {
+        self.write(|book| book.submit(order))
+    }
+
+    pub fn cancel_order(&self, order_id: &str) -> Result<Order, OrderError> {
+        self.write(|book| book.cancel_order(order_id))
+    }
+
+    pub fn get_best_bid(&self) -> Option<u64> {
+        self.read(OrderBook::get_best_bid)
+    }
+
+    pub fn get_best_ask(&self) -> Option<u64> {
+        self.read(OrderBook::get_best_ask)
+    }
+
+    pub fn ladder(&self) -> (Vec<Level>, Vec<Level>) {
+        self.read(OrderBook::ladder)
+    }
+
+    pub fn format_order_book(&self) -> String {
+        self.read(OrderBook::format_order_book)
+    }
+
+    /// Runs `f` with shared access to the book.
+    pub fn read<R>(&self, f: impl FnOnce(&OrderBook) -> R) -> R {
+        f(&self.book.read().expect("order book lock poisoned"))
+    }
+
+    /// Runs `f` with exclusive access to the book.
+    pub fn write<R>(&self, f: impl FnOnce(&mut OrderBook) -> R) -> R {
+        f(&mut self.book.write().expect("order book lock poisoned"))
+    }
+
+    pub fn into_inner(self) -> OrderBook {
+        self.book.into_inner().expect("order book lock poisoned")
+    }
+}
+
+/// Routes orders to the books of each symbol. A symbol can trade on several
+/// venues, with one `OrderBook` per venue; the methods without a venue use
+/// `MatchingEngine::PRIMARY_VENUE`.
+#[derive(Default)]
+pub struct MatchingEngine {
+    /// Books by symbol, then by venue.
+    books: HashMap<String, BTreeMap<String, OrderBook>>,
+    trade_through_protection: bool,
+}
+
+impl MatchingEngine {
+    /// The venue of the books added by `add_book`.
+    pub const PRIMARY_VENUE: &'static str = "PRIMARY";
+
+    pub fn new() -> Self {
+        MatchingEngine {
+            books: HashMap::new(),
+            trade_through_protection: false,
+        }
+    }
+
+    /// Adds an empty book for `symbol` on the primary venue, keeping the
+    /// existing one if present.
+    ///
+    /// Symbols are normalized like order symbols, so `" aapl "` and `"AAPL"`
+    /// refer to the same book.
+    pub fn add_book(&mut self, symbol: String) {
+        self.add_venue_book(symbol, Self::PRIMARY_VENUE);
+    }
+
+    /// Adds an empty book for `symbol` on `venue`, keeping the existing one
+    /// if present.
+    pub fn add_venue_book(&mut self, symbol: String, venue: &str) {
+        let book = OrderBook::new(symbol);
+        self.books
+            .entry(book.symbol.clone())
+            .or_default()
+            .entry(venue.to_string())
+            .or_insert(book);
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.venue_book(symbol, Self::PRIMARY_VENUE)
+    }
+
+    pub fn book_mut(&mut self, symbol: &str) -> Option<&mut OrderBook> {
+        self.venue_book_mut(symbol, Self::PRIMARY_VENUE)
+    }
+
+    pub fn venue_book(&self, symbol: &str, venue: &str) -> Option<&OrderBook> {
+        self.books.get(&normalize_symbol(symbol))?.get(venue)
+    }
+
+    pub fn venue_book_mut(&mut self, symbol: &str, venue: &str) -> Option<&mut OrderBook> {
+        self.books.get_mut(&normalize_symbol(symbol))?.get_mut(venue)
+    }
+
+    /// Rejects, with `OrderError::TradeThrough`, an incoming order that would
+    /// trade on its venue at a price worse than the consolidated best
+    /// displayed price for its symbol. Off by default.
+    pub fn set_trade_through_protection(&mut self, enabled: bool) {
+        self.trade_through_protection = enabled;
+    }
+
+    /// Submits an order to the primary venue's book for its symbol.
+    pub fn submit(&mut self, order: Order) -> OrderOutcome {
+        self.submit_to(Self::PRIMARY_VENUE, order)
+    }
+
+    /// Submits an order to `venue`'s book for its symbol, subject to
+    /// trade-through protection if it is on.
+    pub fn submit_to(&mut self, venue: &str, order: Order) -> OrderOutcome {
+        let trades_through = self.trade_through_protection && self.trades_through(venue, &order);
+
+        match self.books.get_mut(&order.symbol).and_then(|venues| venues.get_mut(venue)) {
+            Some(book) if trades_through => OrderOutcome {
+                order_id: order.id.clone(),
+                status: OrderStatus::Rejected(book.reject(order, OrderError::TradeThrough)),
+                trades: Vec::new(),
+                resting_quantity: Quantity::ZERO,
+            },
+            Some(book) => book.submit(order),
+            None => OrderOutcome {
+                order_id: order.id,
+                status: OrderStatus::Rejected(OrderError::UnknownSymbol),
+                trades: Vec::new(),
+                resting_quantity: Quantity::ZERO,
+            },
+        }
+    }
+
+    /// Returns the best displayed bid and ask for `symbol` across all its
+    /// venues.
+    pub fn consolidated_bbo(&self, symbol: &str) -> (Option<u64>, Option<u64>) {
+        let Some(venues) = self.books.get(&normalize_symbol(symbol)) else {
+            return (None, None);
+        };
+
+        let best_bid = venues.values().filter_map(OrderBook::get_best_bid).max();
+        let best_ask = venues.values().filter_map(OrderBook::get_best_ask).min();
+        (best_bid, best_ask)
+    }
+
+    /// Whether `order`, sent to `venue`, would fill at a price worse than the
+    /// best one another venue displays for its symbol. Every level the order
+    /// would reach on its venue is checked, not only the touch, so a sweep
+    /// past the other venue's price is caught too. Stop orders do not trade
+    /// on arrival, so they never trade through.
+    fn trades_through(&self, venue: &str, order: &Order) -> bool {
+        let Some(venues) = self.books.get(&order.symbol) else {
+            return false;
+        };
+        let Some(book) = venues.get(venue) else {
+            return false;
+        };
+        if order.stop_price.is_some() {
+            return false;
+        }
+
+        let elsewhere = venues.iter().filter(|(name, _)| name.as_str() != venue).map(|(_, other)| other);
+        let best_elsewhere = match order.side {
+            Side::Buy => elsewhere.filter_map(OrderBook::get_best_ask).min(),
+            Side::Sell => elsewhere.filter_map(OrderBook::get_best_bid).max(),
+        };
+        let Some(best_elsewhere) = best_elsewhere else {
+            return false;
+        };
+
+        let levels = book.levels(order.side.opposite());
+        let from_touch: Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)>> = match order.side {
+            Side::Buy => Box::new(levels.iter()),
+            Side::Sell => Box::new(levels.iter().rev()),
+        };
+
+        let mut remaining = order.quantity;
+        for (&price, orders) in from_touch {
+            if remaining.is_zero() || !order.can_trade_at(price, book.cross_rule) {
+                break;
+            }
+            let worse = match order.side {
+                Side::Buy => price > best_elsewhere,
+                Side::Sell => price < best_elsewhere,
+            };
+            if worse {
+                return true;
+            }
+            let level_quantity: Quantity = orders.iter().map(|resting| resting.quantity).sum();
+            remaining = remaining.checked_sub(level_quantity).unwrap_or(Quantity::ZERO);
+        }
+
+        false
+    }
+
+    /// Returns the symbols with a book in this engine, sorted.
+    pub fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.books.keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// Returns the consolidated best bid and best ask of every symbol.
+    pub fn best_bid_ask_all(&self) -> HashMap<String, (Option<u64>, Option<u64>)> {
+        self.books
+            .keys()
+            .map(|symbol| (symbol.clone(), self.consolidated_bbo(symbol)))
+            .collect()
+    }
+}
+
+/// Canonical form of a symbol: surrounding whitespace removed, uppercased.
+fn normalize_symbol(symbol: &str) -> String {
+    symbol.trim().to_uppercase()
+}
+
+/// Whether an incoming order on `side` limited at `limit` can trade against
+/// a resting order at `resting_price`.
+fn crosses(side: Side, limit: u64, resting_price: u64, cross_rule: CrossRule) -> bool {
+    match (side, cross_rule) {
+        (Side::Buy, CrossRule::Inclusive) => resting_price <= limit,
+        (Side::Buy, CrossRule::Strict) => resting_price < limit,
+        (Side::Sell, CrossRule::Inclusive) => resting_price >= limit,
+        (Side::Sell, CrossRule::Strict) => resting_price > limit,
+    }
+}
+
+/// Moves the trade price of an incoming `side` order halfway from the
+/// maker's price towards `own_best`, the best price on the incoming order's
+/// own side, in whole ticks, if that beats `trade_price`. Without a quote
+/// there, or with less than a tick of room, `trade_price` is kept.
+fn improved_price(trade_price: u64, maker_price: u64, own_best: Option<u64>, side: Side, tick: u64) -> u64 {
+    let Some(own_best) = own_best else {
+        return trade_price;
+    };
+    let improvement = maker_price.abs_diff(own_best) / 2 / tick * tick;
+    if improvement == 0 {
+        return trade_price;
+    }
+
+    match side {
+        Side::Buy if own_best < maker_price => trade_price.min(maker_price - improvement),
+        Side::Sell if own_best > maker_price => trade_price.max(maker_price + improvement),
+        _ => trade_price,
+    }
+}
+
+/// Rounds a trade price to `tick`, unless that would take it outside the
+/// range between the maker's price and the aggressor's limit.
+fn on_tick(trade_price: u64, maker_price: u64, aggressor: &Order, tick: u64, rounding_mode: RoundingMode) -> u64 {
+    let limit = match aggressor.order_type {
+        OrderType::Market => maker_price,
+        OrderType::Limit => aggressor.price,
+    };
+    let rounded = rounding_mode.round_to_tick(trade_price, tick);
+
+    if (maker_price.min(limit)..=maker_price.max(limit)).contains(&rounded) {
+        rounded
+    } else {
+        trade_price
+    }
+}
+
+/// Whether trading at `price` is more than `bps` basis points worse for a
+/// taker on `side` than `reference`.
+fn exceeds_slippage(side: Side, reference: u64, price: u64, bps: u32) -> bool {
+    let adverse_move = match side {
+        Side::Buy => price.saturating_sub(reference),
+        Side::Sell => reference.saturating_sub(price),
+    };
+
+    adverse_move as u128 * 10_000 > bps as u128 * reference as u128
+}
+
+/// Inserts `order` into a price level behind every order that `queue_key`
+/// ranks ahead of it or equal to it.
+fn insert_by_priority(orders: &mut VecDeque<Order>, order: Order, trader_priority: &HashMap<String, u8>) {
+    let order_key = queue_key(&order, trader_priority);
+    let index = orders.partition_point(|resting| queue_key(resting, trader_priority) <= order_key);
+    orders.insert(index, order);
+}
+
+/// The sort key of an order within its price level: higher trader priority
+/// first, then orders moved up by `improve_order` (the latest first), then
+/// lower sequence number.
+fn queue_key(order: &Order, trader_priority: &HashMap<String, u8>) -> (Reverse<u8>, Reverse<u64>, u64) {
+    let priority = trader_priority.get(&order.trader_id).copied().unwrap_or(0);
+    (Reverse(priority), Reverse(order.queue_jump), order.seq)
+}
+
+/// Builds a trade, in a recycled one from `pool` if there is one.
+fn pooled_trade(
+    pool: &mut Option<Vec<Trade>>,
+    buy_order: &Order,
+    sell_order: &Order,
+    price: u64,
+    quantity: Quantity,
+    aggressor: Side,
+    timestamp: DateTime<Utc>,
+) -> Trade {
+    match pool.as_mut().and_then(Vec::pop) {
+        Some(mut trade) => {
+            trade.reuse(buy_order, sell_order, price, quantity, aggressor, timestamp);
+            trade
+        }
+        None => Trade::between(buy_order, sell_order, price, quantity, aggressor, timestamp),
+    }
+}
+
+/// Copies `trade`, into a recycled one from `pool` if there is one.
+fn pooled_copy(pool: &mut Option<Vec<Trade>>, trade: &Trade) -> Trade {
+    match pool.as_mut().and_then(Vec::pop) {
+        Some(mut copy) => {
+            copy.clone_from(trade);
+            copy
+        }
+        None => trade.clone(),
+    }
+}
+
+/// Removes the order at `index` from its level once it has no quantity left,
+/// otherwise copies its new quantity into the id index.
+fn settle_resting_order(orders: &mut VecDeque<Order>, index: usize, orders_by_id: &mut HashMap<String, Order>) {
+    if orders[index].quantity.is_zero() {
+        if let Some(filled) = orders.remove(index) {
+            orders_by_id.remove(&filled.id);
+        }
+    } else if let Some(indexed) = orders_by_id.get_mut(&orders[index].id) {
+        indexed.quantity = orders[index].quantity;
+    }
+}
+
+/// Adds a trade to (`direction` 1) or takes it out of (`direction` -1) the
+/// buyer's and seller's net positions.
+fn shift_positions(positions: &mut HashMap<String, i128>, trade: &Trade, direction: i128) {
+    let quantity = trade.quantity.raw() as i128 * direction;
+    *positions.entry(trade.buy_trader_id.clone()).or_default() += quantity;
+    *positions.entry(trade.sell_trader_id.clone()).or_default() -= quantity;
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Quotes and escapes a string as a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Subtracts a fill from an order quantity.
+///
+/// # Panics
+///
+/// Panics if `fill` exceeds `quantity`. That would be a matching bug, and
+/// wrapping around to a huge quantity would silently corrupt the book.
+fn reduce_quantity(quantity: Quantity, fill: Quantity) -> Quantity {
+    quantity
+        .checked_sub(fill)
+        .unwrap_or_else(|| panic!("fill of {} exceeds order quantity {}", fill, quantity))
+}
+
+/// Index of the order a price level matches next, among the orders
+/// `eligible` accepts: the first displayed order, or the first hidden one
+/// when no displayed order is left.
+fn next_to_match(orders: &VecDeque<Order>, eligible: impl Fn(&Order) -> bool) -> Option<usize> {
+    orders
+        .iter()
+        .position(|order| !order.hidden && eligible(order))
+        .or_else(|| orders.iter().position(eligible))
+}
+
+/// Whether an incoming `quantity` may trade against `order`: all-or-none
+/// orders only match an incoming order that can take all of them.
+fn fits_all_or_none(order: &Order, quantity: Quantity) -> bool {
+    !order.all_or_none || order.quantity <= quantity
+}
+
+/// Total displayed quantity of `orders`; hidden orders are not counted.
+fn level_quantity<'a>(orders: impl IntoIterator<Item = &'a Order>) -> Quantity {
+    orders
+        .into_iter()
+        .filter(|order| !order.hidden)
+        .map(|order| order.quantity)
+        .sum()
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and most exchange feed checksums.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Generates a random `TRADER-XXXXX` id using the supplied RNG.
+pub fn generate_random_trader_id<R: Rng + ?Sized>(rng: &mut R) -> String {
+    format!("TRADER-{}", 
+        std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .take(5)
+        .map(char::from)
+        .collect::<String>()
+    )
+}
+
+/// Generates a random order for `symbol` using the supplied RNG.
+///
+/// Passing a seeded `StdRng` yields the same sequence of trader ids, sides,
+/// prices and quantities on every run. Order ids remain random UUIDs.
+pub fn generate_random_order<R: Rng + ?Sized>(rng: &mut R, symbol: &str) -> Order {
+    let side = if rng.gen_bool(0.5) { Side::Buy } else { Side::Sell };
+    
+    // Generate price between 90 and 110
+    let base_price = 100;
+    let price_variation = rng.gen_range(-10..=10);
+    let price = (base_price + price_variation) as u64;
+    
+    // Generate quantity between 1 and 20
+    let quantity = Quantity::from(rng.gen_range(1..=20u32));
+    
+    Order::new(
+        generate_random_trader_id(rng),
+        symbol.to_string(),
+        price,
+        quantity,
+        side,
+    )
+}
+
+/// Runs a random trading simulation against a fresh order book for `symbol`.
+///
+/// Passing a `seed` makes the generated order flow reproducible across runs;
+/// with `None` the generator is seeded from system entropy. `delay` is the
+/// pause between rounds.
+pub fn run_simulation(symbol: &str, rounds: usize, seed: Option<u64>, delay: Duration) {
+    let mut rng = match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut order_book = OrderBook::new(symbol.to_string());
+    
+    println!("Simulating random trading for {}", symbol);
+    println!("=================================");
+    
+    for i in 1..=rounds {
+        println!("\nRound {}", i);
+        
+        // Generate a random order
+        let order = generate_random_order(&mut rng, symbol);
+        println!("Placing {:?} order: {} shares of {} at ${}", 
+            order.side, order.quantity, symbol, order.price);
+        
+        // Place the order and get any resulting trades
+        let trades = order_book.place_order(order);
+        
+        // Report any trades that occurred
+        if !trades.is_empty() {
+            println!("TRADES EXECUTED:");
+            for trade in &trades {
+                println!("  {} shares at ${}", trade.quantity, trade.price);
+            }
+        }
+        
+        // Display the current order book
+        order_book.display_order_book();
+        
+        // Show the current spread
+        println!("{}", order_book.spread_line());
+        
+        // Add a delay between rounds
+        if i < rounds {
+            println!("\nWaiting for next round...");
+            thread::sleep(delay);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn qty(units: u32) -> Quantity {
+        Quantity::from(units)
+    }
+
+    fn limit(trader_id: &str, price: u64, units: u32, side: Side) -> Order {
+        Order::new(trader_id.to_string(), "AAPL".to_string(), price, qty(units), side)
+    }
+
+    fn book() -> OrderBook {
+        OrderBook::new("AAPL".to_string())
+    }
+
+    #[test]
+    fn seeded_rng_repeats_orders_and_trades() {
+        let run = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut book = book();
+            let mut orders = Vec::new();
+            let mut trades = Vec::new();
+            for _ in 0..50 {
+                let order = generate_random_order(&mut rng, "AAPL");
+                orders.push((order.trader_id.clone(), order.side, order.price, order.quantity));
+                trades.extend(book.place_order(order).into_iter().map(|trade| (trade.price, trade.quantity)));
+            }
+            (orders, trades)
+        };
+
+        let (orders, trades) = run(42);
+        assert_eq!(run(42), (orders.clone(), trades.clone()));
+        assert!(!trades.is_empty());
+        assert_ne!(run(7).0, orders);
+    }
+
+    #[test]
+    fn quantity_parses_and_prints_exact_decimals() {
+        let half: Quantity = "0.5".parse().unwrap();
+        assert_eq!(half.to_string(), "0.5");
+        assert_eq!(".25".parse::<Quantity>().unwrap().raw(), 25_000_000);
+        assert_eq!(qty(20).to_string(), "20");
+        assert_eq!("1.000000001".parse::<Quantity>(), Err(QuantityError));
+        assert_eq!("-1".parse::<Quantity>(), Err(QuantityError));
+        assert_eq!(half + half, qty(1));
+    }
+
+    #[test]
+    fn quantity_from_large_whole_number_fails_instead_of_overflowing() {
+        assert_eq!(Quantity::try_from(u64::MAX), Err(QuantityError));
+        assert_eq!(Quantity::try_from(u32::MAX as u64), Ok(Quantity::from(u32::MAX)));
+    }
+
+    #[test]
+    fn fractional_quantities_fill_exactly() {
+        let mut book = book();
+        book.submit(Order::new("S1".to_string(), "AAPL".to_string(), 100, "0.3".parse().unwrap(), Side::Sell));
+        let first = book.submit(Order::new("B1".to_string(), "AAPL".to_string(), 100, "0.1".parse().unwrap(), Side::Buy));
+        assert_eq!(first.status, OrderStatus::Filled);
+        assert_eq!(book.best_ask_with_qty(), Some((100, "0.2".parse().unwrap())));
+
+        let second = book.submit(Order::new("B2".to_string(), "AAPL".to_string(), 100, "0.2".parse().unwrap(), Side::Buy));
+        assert_eq!(second.trades[0].quantity, "0.2".parse().unwrap());
+        assert_eq!(book.best_ask_with_qty(), None);
+    }
+
+    #[test]
+    fn full_side_rejects_resting_orders_but_still_matches() {
+        let mut book = book();
+        book.set_max_orders_per_side(Some(2));
+        book.submit(limit("S1", 101, 10, Side::Sell));
+        book.submit(limit("S2", 102, 10, Side::Sell));
+
+        let outcome = book.submit(limit("S3", 103, 10, Side::Sell));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::BookFull));
+        assert_eq!(book.sell_orders.len(), 2);
+
+        let outcome = book.submit(limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+        assert_eq!(outcome.trades.len(), 1);
+        assert_eq!(book.best_ask_with_qty(), Some((101, qty(5))));
+    }
+
+    #[test]
+    fn crc32_matches_standard_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn depth_checksum_covers_canonical_top_levels() {
+        assert_eq!(book().depth_checksum(5), crc32(b""));
+
+        let mut book = book();
+        book.submit(limit("B1", 99, 5, Side::Buy));
+        book.submit(limit("S1", 101, 2, Side::Sell));
+        book.submit(limit("S2", 102, 3, Side::Sell));
+
+        assert_eq!(book.depth_checksum(1), crc32(b"99:5:101:2"));
+        assert_eq!(book.depth_checksum(2), crc32(b"99:5:101:2:102:3"));
+    }
+
+    #[test]
+    fn slippage_guard_leaves_thin_book_remainder_unfilled() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        book.submit(limit("S2", 101, 5, Side::Sell));
+        book.submit(limit("S3", 120, 10, Side::Sell));
+
+        let order = Order::market("B1".to_string(), "AAPL".to_string(), qty(20), Side::Buy).with_max_slippage_bps(200);
+        let outcome = book.submit(order);
+
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.trades.iter().map(|trade| trade.quantity).sum::<Quantity>(), qty(10));
+        assert_eq!(outcome.resting_quantity, Quantity::ZERO);
+        assert_eq!(book.best_ask_with_qty(), Some((120, qty(10))));
+        assert_eq!(book.get_best_bid(), None);
+    }
+
+    #[test]
+    fn place_order_logs_invalid_orders_instead_of_panicking() {
+        let mut book = book();
+        let other_symbol = Order::new("B1".to_string(), "MSFT".to_string(), 100, qty(5), Side::Buy);
+        let empty = limit("B2", 100, 0, Side::Buy);
+
+        assert!(book.place_order(other_symbol).is_empty());
+        assert!(book.place_order(empty).is_empty());
+
+        let reasons: Vec<OrderError> = book.rejected_orders().iter().map(|(_, error)| error.clone()).collect();
+        assert_eq!(reasons, vec![OrderError::SymbolMismatch, OrderError::ZeroQuantity]);
+        assert_eq!(book.get_best_bid(), None);
+    }
+
+    #[test]
+    fn cancel_all_for_trader_leaves_other_traders_at_same_levels() {
+        let mut book = book();
+        book.submit(limit("T1", 99, 10, Side::Buy));
+        let other_bid = book.submit(limit("T2", 99, 7, Side::Buy)).order_id;
+        book.submit(limit("T1", 101, 10, Side::Sell));
+        let other_ask = book.submit(limit("T2", 101, 3, Side::Sell)).order_id;
+        book.submit(limit("T1", 105, 4, Side::Sell));
+
+        let cancelled = book.cancel_all_for_trader("T1");
+
+        assert_eq!(cancelled.len(), 3);
+        assert!(cancelled.iter().all(|order| order.trader_id == "T1"));
+        assert_eq!(book.ladder(), (vec![(99, qty(7))], vec![(101, qty(3))]));
+        assert!(book.orders_by_id.contains_key(&other_bid));
+        assert!(book.orders_by_id.contains_key(&other_ask));
+        assert_eq!(book.orders_by_id.len(), 2);
+    }
+
+    #[test]
+    fn min_rest_qty_cancels_dust_remainder() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 95, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 100, 100, Side::Buy).with_min_rest_qty(qty(10)));
+
+        assert_eq!(outcome.trades.iter().map(|trade| trade.quantity).sum::<Quantity>(), qty(95));
+        assert_eq!(outcome.resting_quantity, Quantity::ZERO);
+        assert_eq!(book.ladder(), (vec![], vec![]));
+    }
+
+    #[test]
+    fn padded_lowercase_symbol_routes_to_normalized_book() {
+        let mut engine = MatchingEngine::new();
+        engine.add_book("AAPL".to_string());
+
+        let order = Order::new("B1".to_string(), " aapl ".to_string(), 100, qty(5), Side::Buy);
+        let outcome = engine.submit(order);
+
+        assert_eq!(outcome.status, OrderStatus::Resting);
+        assert_eq!(engine.book("AAPL").and_then(|book| book.get_best_bid()), Some(100));
+
+        let blank = Order::new("B1".to_string(), "  ".to_string(), 100, qty(5), Side::Buy);
+        assert_eq!(book().submit(blank).status, OrderStatus::Rejected(OrderError::EmptySymbol));
+    }
+
+    #[test]
+    fn trade_sender_delivers_trades_to_receiver_thread() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let receiver = thread::spawn(move || rx.iter().map(|trade: Trade| (trade.price, trade.quantity)).collect::<Vec<_>>());
+
+        let mut book = book();
+        book.with_trade_sender(tx);
+        book.place_order(limit("S1", 100, 5, Side::Sell));
+        book.place_order(limit("S2", 101, 5, Side::Sell));
+        let trades = book.place_order(limit("B1", 101, 8, Side::Buy));
+        assert_eq!(trades.len(), 2);
+        drop(book);
+
+        assert_eq!(receiver.join().unwrap(), vec![(100, qty(5)), (101, qty(3))]);
+    }
+
+    #[test]
+    fn sync_book_serves_readers_while_a_writer_matches() {
+        let book = SyncOrderBook::new("AAPL".to_string());
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..200 {
+                    book.place_order(limit("S1", 100 + i % 5, 1, Side::Sell));
+                    book.place_order(limit("B1", 104, 1, Side::Buy));
+                }
+            });
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..200 {
+                        let (bids, asks) = book.ladder();
+                        let crossed = matches!((bids.first(), asks.first()), (Some(bid), Some(ask)) if bid.0 >= ask.0);
+                        assert!(!crossed);
+                    }
+                });
+            }
+        });
+
+        let book = book.into_inner();
+        assert_eq!(book.trades.len(), 200);
+        assert_eq!(book.ladder(), (vec![], vec![]));
+    }
+
+    #[test]
+    fn last_trade_and_mid_trigger_stops_differently() {
+        let run = |trigger_ref: StopTriggerRef| {
+            let mut book = book();
+            book.set_stop_trigger_ref(trigger_ref);
+            book.submit(limit("S0", 100, 1, Side::Sell));
+            book.submit(limit("B0", 100, 1, Side::Buy));
+            book.submit(limit("B1", 100, 5, Side::Buy));
+            book.submit(limit("S1", 110, 5, Side::Sell));
+            book.submit(Order::stop("T1".to_string(), "AAPL".to_string(), 104, qty(2), Side::Buy));
+            book.submit(limit("B2", 90, 1, Side::Buy));
+            book
+        };
+
+        let last_trade = run(StopTriggerRef::LastTrade);
+        assert_eq!(last_trade.pending_stops().len(), 1);
+        assert_eq!(last_trade.trades.len(), 1);
+
+        let mid = run(StopTriggerRef::Mid);
+        assert!(mid.pending_stops().is_empty());
+        assert_eq!(mid.trades.len(), 2);
+        assert_eq!(mid.best_ask_with_qty(), Some((110, qty(3))));
+    }
+
+    #[test]
+    fn pegged_buy_follows_best_bid_up() {
+        let mut book = book();
+        book.submit(limit("B1", 100, 5, Side::Buy));
+        let pegged = book.submit(limit("P1", 1, 3, Side::Buy).with_peg(PegType::BestBid, 0)).order_id;
+        assert_eq!(book.orders_by_id[&pegged].price, 100);
+
+        book.submit(limit("B2", 101, 5, Side::Buy));
+        assert_eq!(book.orders_by_id[&pegged].price, 101);
+        book.submit(limit("B3", 102, 5, Side::Buy));
+        assert_eq!(book.orders_by_id[&pegged].price, 102);
+        assert_eq!(book.best_bid_with_qty(), Some((102, qty(8))));
+
+        book.cancel_all_for_trader("B3");
+        assert_eq!(book.orders_by_id[&pegged].price, 101);
+    }
+
+    #[test]
+    fn rounding_mode_changes_fee_totals_for_same_fill() {
+        let fees = |rounding_mode: RoundingMode| {
+            let mut book = book();
+            book.set_rounding_mode(rounding_mode);
+            book.set_fee_bps(25);
+            book.submit(limit("S1", 101, 3, Side::Sell));
+            book.submit(limit("S2", 102, 3, Side::Sell));
+            book.submit(limit("B1", 102, 6, Side::Buy));
+            book.total_fees()
+        };
+
+        // Fees of 0.7575 and 0.765 per trade
+        assert_eq!(fees(RoundingMode::Floor), 0);
+        assert_eq!(fees(RoundingMode::Ceil), 2);
+    }
+
+    #[test]
+    fn hidden_sell_is_not_displayed_but_fills_incoming_buy() {
+        let mut book = book();
+        book.submit(limit("S1", 101, 5, Side::Sell).with_hidden());
+        book.submit(limit("S2", 102, 5, Side::Sell));
+
+        assert_eq!(book.ladder(), (vec![], vec![(102, qty(5))]));
+        assert_eq!(book.get_best_ask(), Some(102));
+        assert!(!book.format_order_book().contains("101"));
+
+        let outcome = book.submit(limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+        assert_eq!(outcome.trades[0].sell_trader_id, "S1");
+        assert_eq!(outcome.trades[0].price, 101);
+    }
+
+    #[test]
+    fn hidden_order_fills_after_displayed_order_at_same_price() {
+        let mut book = book();
+        book.submit(limit("HIDDEN", 101, 5, Side::Sell).with_hidden());
+        book.submit(limit("SHOWN", 101, 5, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 101, 7, Side::Buy));
+        let sellers: Vec<_> = outcome.trades.iter().map(|trade| (trade.sell_trader_id.as_str(), trade.quantity)).collect();
+        assert_eq!(sellers, vec![("SHOWN", qty(5)), ("HIDDEN", qty(2))]);
+    }
+
+    #[test]
+    fn order_at_fresh_price_diffs_to_one_added_level() {
+        let mut book = book();
+        book.submit(limit("B1", 99, 5, Side::Buy));
+        book.submit(limit("S1", 101, 5, Side::Sell));
+        let prev = book.snapshot();
+
+        book.submit(limit("S2", 103, 4, Side::Sell));
+        let curr = book.snapshot();
+        let delta = diff(&prev, &curr);
+
+        assert_eq!(
+            delta,
+            BookDelta {
+                bids: vec![],
+                asks: vec![LevelDelta { price: 103, change: LevelChange::Added(qty(4)) }],
+            }
+        );
+        let mut applied = prev.clone();
+        applied.apply(&delta);
+        assert_eq!(applied, curr);
+    }
+
+    #[test]
+    fn trade_price_policy_sets_crossing_price() {
+        let price = |policy: TradePricePolicy| {
+            let mut book = book();
+            book.set_trade_price_policy(policy);
+            book.submit(limit("S1", 100, 5, Side::Sell));
+            book.submit(limit("B1", 104, 5, Side::Buy)).trades[0].price
+        };
+
+        assert_eq!(price(TradePricePolicy::Maker), 100);
+        assert_eq!(price(TradePricePolicy::Aggressor), 104);
+        assert_eq!(price(TradePricePolicy::Midpoint), 102);
+    }
+
+    #[test]
+    fn decrement_both_shrinks_orders_without_trading() {
+        let mut book = book();
+        book.set_stp_mode(StpMode::DecrementBoth);
+        let resting = book.submit(limit("T1", 100, 10, Side::Sell)).order_id;
+
+        let outcome = book.submit(limit("T1", 100, 4, Side::Buy));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.status, OrderStatus::Cancelled);
+        assert_eq!(book.orders_by_id[&resting].quantity, qty(6));
+
+        let outcome = book.submit(limit("T1", 100, 15, Side::Buy));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.status, OrderStatus::Resting);
+        assert_eq!(outcome.resting_quantity, qty(9));
+        assert!(!book.orders_by_id.contains_key(&resting));
+        assert_eq!(book.ladder(), (vec![(100, qty(9))], vec![]));
+        assert_eq!(book.trade_count(), 0);
+    }
+
+    #[test]
+    fn market_order_stopped_by_self_trade_prevention_is_cancelled() {
+        let mut book = book();
+        book.set_stp_mode(StpMode::CancelIncoming);
+        book.submit(limit("T1", 100, 5, Side::Sell));
+
+        let outcome = book.submit(Order::market("T1".to_string(), "AAPL".to_string(), qty(5), Side::Buy));
+
+        assert_eq!(outcome.status, OrderStatus::Cancelled);
+        assert!(book.rejected_orders().is_empty());
+        assert_eq!(book.best_ask_with_qty(), Some((100, qty(5))));
+    }
+
+    #[test]
+    fn trades_csv_parses_back_to_known_rows() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        let second_seller = Order::new("S2".to_string(), "AAPL".to_string(), 101, "2.5".parse().unwrap(), Side::Sell);
+        let sell_id = book.submit(second_seller).order_id;
+        let outcome = book.submit(limit("B1", 101, 10, Side::Buy));
+        let (buy_id, trades) = (outcome.order_id, outcome.trades);
+
+        let csv = book.trades_to_csv();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|line| line.split(',').collect()).collect();
+
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| row.len() == 7));
+        assert_eq!(rows[0], ["id", "symbol", "price", "quantity", "buy_order_id", "sell_order_id", "timestamp"]);
+        let timestamp = trades[1].timestamp.to_rfc3339();
+        assert_eq!(rows[2], [trades[1].id.as_str(), "AAPL", "101", "2.5", buy_id.as_str(), sell_id.as_str(), timestamp.as_str()]);
+        assert_eq!(DateTime::parse_from_rfc3339(rows[1][6]).unwrap(), trades[0].timestamp);
+    }
+
+    #[test]
+    fn csv_field_quotes_delimiters() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn uncross_clears_at_volume_maximizing_price() {
+        let mut book = book();
+        for (price, units) in [(103, 10), (102, 10), (101, 10)] {
+            book.add_for_auction(limit("B", price, units, Side::Buy)).unwrap();
+        }
+        for (price, units) in [(100, 10), (101, 15), (102, 15)] {
+            book.add_for_auction(limit("S", price, units, Side::Sell)).unwrap();
+        }
+        assert_eq!((book.get_best_bid(), book.get_best_ask()), (Some(103), Some(100)));
+
+        // Matched volume is 10 at 100, 25 at 101, 20 at 102 and 10 at 103
+        let (price, trades) = book.uncross();
+
+        assert_eq!(price, Some(101));
+        assert!(trades.iter().all(|trade| trade.price == 101));
+        assert_eq!(trades.iter().map(|trade| trade.quantity).sum::<Quantity>(), qty(25));
+        assert_eq!(book.ladder(), (vec![(101, qty(5))], vec![(102, qty(15))]));
+    }
+
+    #[test]
+    fn fills_for_order_lists_fills_across_counterparties() {
+        let mut book = book();
+        let resting = book.submit(limit("S1", 100, 30, Side::Sell)).order_id;
+        for (trader_id, units) in [("B1", 5), ("B2", 10), ("B3", 7)] {
+            book.submit(limit(trader_id, 100, units, Side::Buy));
+        }
+
+        let fills = book.fills_for_order(&resting);
+        let buyers: Vec<_> = fills.iter().map(|trade| (trade.buy_trader_id.as_str(), trade.quantity)).collect();
+        assert_eq!(buyers, vec![("B1", qty(5)), ("B2", qty(10)), ("B3", qty(7))]);
+        assert!(book.fills_for_order("unknown").is_empty());
+    }
+
+    #[test]
+    fn rate_limit_rejects_order_over_cap() {
+        let now = std::sync::Arc::new(std::sync::Mutex::new(Utc::now()));
+        let mut book = book();
+        let clock = now.clone();
+        book.set_clock(move || *clock.lock().unwrap());
+        book.set_rate_limit(Some((3, Duration::from_secs(1))));
+
+        for price in 90..93 {
+            assert_eq!(book.submit(limit("T1", price, 1, Side::Buy)).status, OrderStatus::Resting);
+        }
+        let outcome = book.submit(limit("T1", 93, 1, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::RateLimited));
+        assert_eq!(book.submit(limit("T2", 93, 1, Side::Buy)).status, OrderStatus::Resting);
+
+        *now.lock().unwrap() += chrono::Duration::seconds(2);
+        assert_eq!(book.submit(limit("T1", 94, 1, Side::Buy)).status, OrderStatus::Resting);
+    }
+
+    #[test]
+    fn book_clock_stamps_orders_and_trades() {
+        let start = Utc::now() - chrono::Duration::days(1);
+        let now = std::sync::Arc::new(std::sync::Mutex::new(start));
+        let mut book = book();
+        let clock = now.clone();
+        book.set_clock(move || *clock.lock().unwrap());
+
+        book.submit(limit("S1", 100, 10, Side::Sell));
+        book.submit(limit("B1", 100, 4, Side::Buy));
+        *now.lock().unwrap() += chrono::Duration::seconds(10);
+        book.submit(limit("B2", 100, 6, Side::Buy));
+
+        let later = start + chrono::Duration::seconds(10);
+        assert_eq!(book.trades.iter().map(|trade| trade.timestamp).collect::<Vec<_>>(), vec![start, later]);
+        let first_only: Vec<_> = book.trades_between(start, start + chrono::Duration::seconds(5)).iter().map(|trade| trade.quantity).collect();
+        assert_eq!(first_only, vec![qty(4)]);
+        assert_eq!(book.trades_between(later, later).len(), 1);
+
+        let resting = book.submit(limit("S2", 101, 1, Side::Sell)).order_id;
+        assert_eq!(book.orders_by_id[&resting].timestamp, later);
+    }
+
+    #[test]
+    fn bust_trade_updates_volume_and_vwap() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 10, Side::Sell));
+        book.submit(limit("S2", 110, 10, Side::Sell));
+        let trades = book.submit(limit("B1", 110, 20, Side::Buy)).trades;
+        assert_eq!(book.total_volume(), qty(20));
+        assert_eq!(book.vwap(), Some(105));
+
+        let busted = book.bust_trade(&trades[1].id).unwrap();
+
+        assert_eq!(busted.price, 110);
+        assert_eq!(book.total_volume(), qty(10));
+        assert_eq!(book.vwap(), Some(100));
+        assert_eq!(book.trade_count(), 1);
+        assert_eq!(book.bust_trade(&trades[1].id).unwrap_err(), OrderError::TradeNotFound);
+    }
+
+    #[test]
+    fn book_state_reports_empty_and_one_sided() {
+        let mut book = book();
+        assert_eq!(book.book_state(), BookState::Empty);
+        book.submit(limit("B1", 99, 5, Side::Buy));
+        assert_eq!(book.book_state(), BookState::OneSided);
+    }
+
+    #[test]
+    fn book_state_reports_normal() {
+        let mut book = book();
+        book.submit(limit("B1", 99, 5, Side::Buy));
+        book.submit(limit("S1", 101, 5, Side::Sell));
+        assert_eq!(book.book_state(), BookState::Normal);
+    }
+
+    #[test]
+    fn book_state_reports_locked() {
+        let mut book = book();
+        book.add_for_auction(limit("B1", 100, 5, Side::Buy)).unwrap();
+        book.add_for_auction(limit("S1", 100, 5, Side::Sell)).unwrap();
+        assert_eq!(book.book_state(), BookState::Locked);
+    }
+
+    #[test]
+    fn book_state_reports_crossed() {
+        let mut book = book();
+        book.add_for_auction(limit("B1", 101, 5, Side::Buy)).unwrap();
+        book.add_for_auction(limit("S1", 100, 5, Side::Sell)).unwrap();
+        assert_eq!(book.book_state(), BookState::Crossed);
+    }
+
+    #[test]
+    fn high_priority_trader_fills_before_earlier_ordinary_order() {
+        let mut book = book();
+        book.set_trader_priority("MM", 5);
+        book.submit(limit("ORDINARY", 100, 5, Side::Buy));
+        book.submit(limit("MM", 100, 5, Side::Buy));
+
+        let outcome = book.submit(limit("S1", 100, 7, Side::Sell));
+        let buyers: Vec<_> = outcome.trades.iter().map(|trade| (trade.buy_trader_id.as_str(), trade.quantity)).collect();
+        assert_eq!(buyers, vec![("MM", qty(5)), ("ORDINARY", qty(2))]);
+    }
+
+    #[test]
+    fn from_resting_orders_rejects_crossed_or_duplicate_orders() {
+        let bid = limit("B1", 99, 5, Side::Buy);
+        let book = OrderBook::from_resting_orders("AAPL".to_string(), vec![bid.clone(), limit("S1", 101, 5, Side::Sell)]).unwrap();
+        assert_eq!(book.ladder(), (vec![(99, qty(5))], vec![(101, qty(5))]));
+
+        let crossed = OrderBook::from_resting_orders("AAPL".to_string(), vec![bid.clone(), limit("S1", 99, 5, Side::Sell)]);
+        assert_eq!(crossed.err(), Some(OrderError::CrossedBook));
+        let duplicate = OrderBook::from_resting_orders("AAPL".to_string(), vec![bid.clone(), bid]);
+        assert_eq!(duplicate.err(), Some(OrderError::DuplicateOrderId));
+    }
+
+    #[test]
+    fn submit_rejects_an_order_id_already_resting() {
+        let mut book = book();
+        let order = limit("B1", 99, 5, Side::Buy);
+        book.submit(order.clone());
+
+        assert_eq!(book.submit(order).status, OrderStatus::Rejected(OrderError::DuplicateOrderId));
+        assert_eq!(book.best_bid_with_qty(), Some((99, qty(5))));
+    }
+
+    #[test]
+    fn incoming_order_side_labels_trade_aggressor() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        let trades = book.submit(limit("B1", 100, 2, Side::Buy)).trades;
+        assert_eq!(trades[0].aggressor(), Side::Buy);
+
+        book.submit(limit("B2", 99, 5, Side::Buy));
+        let trades = book.submit(limit("S2", 99, 2, Side::Sell)).trades;
+        assert_eq!(trades[0].aggressor(), Side::Sell);
+    }
+
+    fn thin_book(policy: MarketUnfilledPolicy) -> OrderBook {
+        let mut book = book();
+        book.set_market_unfilled_policy(policy);
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        book.submit(limit("S2", 101, 5, Side::Sell));
+        book
+    }
+
+    fn market_buy(units: u32) -> Order {
+        Order::market("B1".to_string(), "AAPL".to_string(), qty(units), Side::Buy)
+    }
+
+    #[test]
+    fn cancel_policy_drops_market_remainder() {
+        let mut book = thin_book(MarketUnfilledPolicy::Cancel);
+        let outcome = book.submit(market_buy(15));
+
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.trades.len(), 2);
+        assert_eq!(outcome.resting_quantity, Quantity::ZERO);
+        assert_eq!(book.ladder(), (vec![], vec![]));
+    }
+
+    #[test]
+    fn convert_to_limit_policy_rests_remainder_at_last_fill_price() {
+        let mut book = thin_book(MarketUnfilledPolicy::ConvertToLimit);
+        let outcome = book.submit(market_buy(15));
+
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.resting_quantity, qty(5));
+        assert_eq!(book.ladder(), (vec![(101, qty(5))], vec![]));
+    }
+
+    #[test]
+    fn reject_policy_refuses_market_order_it_cannot_fill() {
+        let mut book = thin_book(MarketUnfilledPolicy::Reject);
+        let outcome = book.submit(market_buy(15));
+
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::InsufficientLiquidity));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(book.ladder(), (vec![], vec![(100, qty(5)), (101, qty(5))]));
+
+        assert_eq!(book.submit(market_buy(10)).status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn min_exec_qty_met_fills_and_rests_remainder() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 6, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 100, 10, Side::Buy).with_min_exec_qty(qty(5)));
+
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.trades[0].quantity, qty(6));
+        assert_eq!(outcome.resting_quantity, qty(4));
+        assert_eq!(book.ladder(), (vec![(100, qty(4))], vec![]));
+    }
+
+    #[test]
+    fn min_exec_qty_not_met_rejects_without_trading() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 3, Side::Sell));
+        book.submit(limit("S2", 101, 5, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 100, 10, Side::Buy).with_min_exec_qty(qty(5)));
+
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::InsufficientLiquidity));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(book.ladder(), (vec![], vec![(100, qty(3)), (101, qty(5))]));
+    }
+
+    #[test]
+    fn id_generator_restamps_submitted_orders() {
+        let mut book = book();
+        book.set_id_generator(SequentialIdGenerator::default());
+
+        let first = book.submit(limit("alice", 100, 1, Side::Sell));
+        let second = book.submit(limit("bob", 101, 1, Side::Sell));
+
+        assert_eq!(first.order_id, "ORD-1");
+        assert_eq!(second.order_id, "ORD-2");
+        assert_eq!(book.queue_position("ORD-1"), Some(Quantity::ZERO));
+    }
+
+    #[test]
+    fn new_with_id_rejects_an_id_already_in_use() {
+        let mut book = book();
+        let make = || Order::new_with_id("A-1".to_string(), "alice".to_string(), "AAPL".to_string(), 100, qty(1), Side::Sell);
+
+        book.submit(make());
+        let outcome = book.submit(make());
+
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::DuplicateOrderId));
+    }
+
+    fn first_buyer_filled(book: &mut OrderBook) -> String {
+        book.submit(limit("SELLER", 100, 1, Side::Sell)).trades[0].buy_order_id.clone()
+    }
+
+    #[test]
+    fn amend_down_at_same_price_keeps_priority() {
+        let mut book = book();
+        let first = book.submit(limit("B1", 100, 10, Side::Buy)).order_id;
+        book.submit(limit("B2", 100, 10, Side::Buy));
+
+        assert!(book.amend_order(&first, 100, qty(5)).unwrap().is_empty());
+
+        assert_eq!(book.orders_by_id[&first].quantity, qty(5));
+        assert_eq!(first_buyer_filled(&mut book), first);
+    }
+
+    #[test]
+    fn amend_up_or_to_new_price_resets_priority() {
+        let mut book = book();
+        let first = book.submit(limit("B1", 100, 10, Side::Buy)).order_id;
+        let second = book.submit(limit("B2", 100, 10, Side::Buy)).order_id;
+
+        book.amend_order(&first, 100, qty(15)).unwrap();
+
+        assert!(!book.orders_by_id.contains_key(&first));
+        assert_eq!(book.best_bid_with_qty(), Some((100, qty(25))));
+        assert_eq!(first_buyer_filled(&mut book), second);
+
+        book.amend_order(&second, 99, qty(9)).unwrap();
+        assert_eq!(book.ladder().0, vec![(100, qty(15)), (99, qty(9))]);
+    }
+
+    #[test]
+    fn rejected_amend_restores_original_order() {
+        let mut book = book();
+        let now = Utc::now();
+        book.set_clock(move || now);
+        book.set_rate_limit(Some((2, Duration::from_secs(60))));
+        let first = book.submit(limit("B1", 100, 10, Side::Buy)).order_id;
+        book.submit(limit("B2", 100, 10, Side::Buy));
+        book.submit(limit("B1", 98, 10, Side::Buy));
+
+        assert_eq!(book.amend_order(&first, 101, qty(10)).unwrap_err(), OrderError::RateLimited);
+
+        assert_eq!(book.orders_by_id[&first].price, 100);
+        assert_eq!(first_buyer_filled(&mut book), first);
+    }
+
+    #[test]
+    fn replacing_amend_carries_order_terms_over() {
+        let mut book = book();
+        let original = book.submit(limit("B1", 100, 10, Side::Buy).with_hidden()).order_id;
+
+        book.amend_order(&original, 101, qty(10)).unwrap();
+
+        let replacement = book.orders_by_id.values().next().unwrap();
+        assert_ne!(replacement.id, original);
+        assert_eq!(replacement.price, 101);
+        assert!(replacement.is_hidden());
+        assert_eq!(book.get_best_bid(), None);
+    }
+
+    #[test]
+    fn cumulative_depth_accumulates_from_the_touch() {
+        let mut book = book();
+        book.submit(limit("S1", 101, 2, Side::Sell));
+        book.submit(limit("S2", 102, 3, Side::Sell));
+        book.submit(limit("S3", 101, 1, Side::Sell));
+
+        assert_eq!(book.cumulative_depth(Side::Sell), vec![(101, qty(3)), (102, qty(6))]);
+        assert!(book.cumulative_depth(Side::Buy).is_empty());
+    }
+
+    #[test]
+    fn amendment_history_records_reduce_replace_and_cancel() {
+        let mut book = book();
+        let id = book.submit(limit("B1", 100, 10, Side::Buy)).order_id;
+
+        book.reduce_order(&id, qty(4)).unwrap();
+        book.amend_order(&id, 99, qty(6)).unwrap();
+
+        let history = book.amendment_history(&id);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].kind(), AmendmentKind::Reduce);
+        assert_eq!(history[0].new_quantity(), qty(6));
+        assert_eq!(history[1].kind(), AmendmentKind::Replace);
+        assert_eq!(history[1].new_price(), 99);
+
+        let replacement = history[1].replacement_id().unwrap().to_string();
+        book.cancel_order(&replacement).unwrap();
+        assert_eq!(book.amendment_history(&replacement)[0].kind(), AmendmentKind::Cancel);
+    }
+
+    #[test]
+    fn resting_all_or_none_skips_partial_taker_but_fills_full_one() {
+        let mut book = book();
+        let aon = book.submit(limit("AON", 100, 50, Side::Sell).with_all_or_none()).order_id;
+
+        let outcome = book.submit(limit("B1", 100, 30, Side::Buy));
+        assert!(outcome.trades.is_empty());
+        assert_eq!(outcome.status, OrderStatus::Resting);
+        assert_eq!(book.orders_by_id[&aon].quantity, qty(50));
+        book.cancel_order(&outcome.order_id).unwrap();
+
+        let outcome = book.submit(limit("B2", 100, 50, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+        assert_eq!(outcome.trades[0].sell_order_id, aon);
+        assert_eq!(outcome.trades[0].quantity, qty(50));
+    }
+
+    #[test]
+    fn twap_weights_prices_by_time_on_a_fixed_clock() {
+        let start = Utc::now() - chrono::Duration::days(1);
+        let now = std::sync::Arc::new(std::sync::Mutex::new(start));
+        let mut book = book();
+        let clock = now.clone();
+        book.set_clock(move || *clock.lock().unwrap());
+
+        book.submit(limit("S1", 100, 1, Side::Sell));
+        book.submit(limit("S2", 110, 1, Side::Sell));
+        book.submit(limit("B1", 100, 1, Side::Buy));
+        *now.lock().unwrap() += chrono::Duration::seconds(30);
+        book.submit(limit("B2", 110, 1, Side::Buy));
+
+        let end = start + chrono::Duration::seconds(40);
+        assert_eq!(book.twap(start, end), Some(102.5));
+        assert_eq!(book.twap(end, end), None);
+    }
+
+    #[test]
+    fn snapshots_export_as_json_with_their_timestamps() {
+        let at = Utc::now();
+        let mut book = book();
+        book.set_clock(move || at);
+        book.submit(limit("B1", 99, 2, Side::Buy));
+        book.record_snapshot();
+        book.submit(limit("S1", 101, 3, Side::Sell));
+        book.record_snapshot();
+
+        assert_eq!(book.snapshots().len(), 2);
+        let expected = format!(
+            "[{{\"timestamp\":\"{0}\",\"symbol\":\"AAPL\",\"bids\":[[99,2]],\"asks\":[]}},\
+             {{\"timestamp\":\"{0}\",\"symbol\":\"AAPL\",\"bids\":[[99,2]],\"asks\":[[101,3]]}}]",
+            at.to_rfc3339()
+        );
+        assert_eq!(book.snapshots_to_json(), expected);
+    }
+
+    #[test]
+    fn inclusive_cross_rule_matches_equal_prices() {
+        for side in [Side::Buy, Side::Sell] {
+            let mut book = book();
+            book.submit(limit("MAKER", 100, 5, side.opposite()));
+            let outcome = book.submit(limit("TAKER", 100, 5, side));
+            assert_eq!(outcome.status, OrderStatus::Filled);
+        }
+    }
+
+    #[test]
+    fn strict_cross_rule_rests_equal_prices_and_matches_better_ones() {
+        for (side, better) in [(Side::Buy, 101), (Side::Sell, 99)] {
+            let mut book = book();
+            book.set_cross_rule(CrossRule::Strict);
+            book.submit(limit("MAKER", 100, 5, side.opposite()));
+
+            let outcome = book.submit(limit("TAKER", 100, 5, side));
+            assert!(outcome.trades.is_empty());
+            assert_eq!(outcome.status, OrderStatus::Resting);
+
+            let outcome = book.submit(limit("TAKER", better, 5, side));
+            assert_eq!(outcome.trades[0].price, 100);
+        }
+    }
+
+    #[test]
+    fn place_sliced_spaces_children_and_puts_the_remainder_last() {
+        let start = Utc::now() - chrono::Duration::days(1);
+        let mut book = book();
+        book.set_clock(move || start);
+        book.submit(limit("S1", 100, 20, Side::Sell));
+
+        let trades = book.place_sliced(limit("B1", 100, 10, Side::Buy), 3, Duration::from_secs(60));
+
+        let quantities: Vec<_> = trades.iter().map(|trade| trade.quantity).collect();
+        assert_eq!(quantities, vec![qty(3), qty(3), qty(4)]);
+        let offsets: Vec<_> = trades.iter().map(|trade| (trade.timestamp - start).num_seconds()).collect();
+        assert_eq!(offsets, vec![0, 60, 120]);
+
+        let resting = book.submit(limit("B2", 99, 1, Side::Buy)).order_id;
+        assert_eq!(book.orders_by_id[&resting].timestamp, start);
+    }
+
+    #[test]
+    fn wide_spread_rejects_market_order() {
+        let mut book = book();
+        book.set_max_market_spread_bps(Some(500));
+        book.submit(limit("B1", 90, 5, Side::Buy));
+        book.submit(limit("S1", 110, 5, Side::Sell));
+
+        let outcome = book.submit(market_buy(1));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::SpreadTooWide));
+        assert!(outcome.trades.is_empty());
+
+        book.submit(limit("S2", 92, 5, Side::Sell));
+        assert_eq!(book.submit(market_buy(1)).status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn recent_volume_and_prices_cover_the_last_trades() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 1, Side::Sell));
+        book.submit(limit("S2", 101, 2, Side::Sell));
+        book.submit(limit("S3", 102, 3, Side::Sell));
+        book.submit(limit("B1", 102, 6, Side::Buy));
+
+        assert_eq!(book.recent_volume(2), qty(5));
+        assert_eq!(book.recent_trade_prices(2), vec![101, 102]);
+        assert_eq!(book.recent_volume(10), qty(6));
+    }
+
+    #[test]
+    fn validate_against_log_reports_the_first_difference() {
+        let orders = vec![limit("S1", 100, 5, Side::Sell), limit("B1", 100, 3, Side::Buy)];
+        let mut book = book();
+        for order in &orders {
+            book.submit(order.clone());
+        }
+        let mut expected = book.drain_trades();
+
+        assert_eq!(OrderBook::validate_against_log("AAPL".to_string(), &orders, &expected), Ok(()));
+
+        expected[0].quantity = qty(2);
+        let error = OrderBook::validate_against_log("AAPL".to_string(), &orders, &expected).unwrap_err();
+        assert!(error.starts_with("trade 0: expected 2 @ 100"));
+    }
+
+    #[test]
+    fn off_tick_midpoint_price_snaps_to_tick() {
+        let price = |rounding_mode: RoundingMode| {
+            let mut book = book();
+            book.set_trade_price_policy(TradePricePolicy::Midpoint);
+            book.set_tick_size(5);
+            book.set_rounding_mode(rounding_mode);
+            book.submit(limit("S1", 100, 5, Side::Sell));
+            book.submit(limit("B1", 115, 5, Side::Buy)).trades[0].price
+        };
+
+        // The midpoint of 100 and 115 is 107.5
+        assert_eq!(price(RoundingMode::HalfUp), 110);
+        assert_eq!(price(RoundingMode::Floor), 105);
+    }
+
+    #[test]
+    fn top_trader_quotes_sums_each_traders_orders_per_price() {
+        let mut book = book();
+        book.submit(limit("B1", 100, 2, Side::Buy));
+        book.submit(limit("B2", 100, 1, Side::Buy));
+        book.submit(limit("B1", 100, 3, Side::Buy));
+        book.submit(limit("B3", 99, 4, Side::Buy));
+        book.submit(limit("B4", 101, 1, Side::Buy).with_hidden());
+
+        assert_eq!(
+            book.top_trader_quotes(Side::Buy, 2),
+            vec![("B1".to_string(), 100, qty(5)), ("B2".to_string(), 100, qty(1))]
+        );
+    }
+
+    #[test]
+    fn try_new_rejects_empty_trader_id() {
+        let order = Order::try_new(" ".to_string(), "AAPL".to_string(), 100, qty(5), Side::Buy);
+        assert_eq!(order.unwrap_err(), OrderError::EmptyTraderId);
+        assert!(Order::try_new("T1".to_string(), "AAPL".to_string(), 100, qty(5), Side::Buy).is_ok());
+    }
+
+    #[test]
+    fn sweep_to_price_takes_two_levels_without_resting() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        book.submit(limit("S2", 101, 7, Side::Sell));
+        book.submit(limit("S3", 103, 5, Side::Sell));
+        assert_eq!(book.available_quantity_against(Side::Buy, 101), qty(12));
+
+        let trades = book.sweep_to_price("B1", Side::Buy, 101);
+
+        let fills: Vec<_> = trades.iter().map(|trade| (trade.price, trade.quantity)).collect();
+        assert_eq!(fills, vec![(100, qty(5)), (101, qty(7))]);
+        assert_eq!(book.ladder(), (vec![], vec![(103, qty(5))]));
+    }
+
+    #[test]
+    fn trade_through_protection_rejects_worse_venue() {
+        let mut engine = MatchingEngine::new();
+        engine.add_book("AAPL".to_string());
+        engine.add_venue_book("AAPL".to_string(), "ALT");
+        engine.set_trade_through_protection(true);
+        engine.submit(limit("S1", 101, 5, Side::Sell));
+        engine.submit_to("ALT", limit("S2", 100, 5, Side::Sell));
+        assert_eq!(engine.consolidated_bbo("AAPL"), (None, Some(100)));
+
+        let outcome = engine.submit(limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::TradeThrough));
+        assert_eq!(engine.book("AAPL").unwrap().best_ask_with_qty(), Some((101, qty(5))));
+
+        let outcome = engine.submit_to("ALT", limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+        assert_eq!(outcome.trades[0].price, 100);
+
+        let outcome = engine.submit(limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+    }
+
+    #[test]
+    fn trade_through_protection_checks_every_level_a_sweep_reaches() {
+        let mut engine = MatchingEngine::new();
+        engine.add_book("AAPL".to_string());
+        engine.add_venue_book("AAPL".to_string(), "ALT");
+        engine.set_trade_through_protection(true);
+        engine.submit(limit("S1", 100, 5, Side::Sell));
+        engine.submit(limit("S2", 102, 5, Side::Sell));
+        engine.submit_to("ALT", limit("S3", 101, 5, Side::Sell));
+
+        // The touch at 100 beats ALT's 101, but the second 5 would fill at 102
+        let outcome = engine.submit(limit("B1", 102, 10, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::TradeThrough));
+        assert!(outcome.trades.is_empty());
+
+        let outcome = engine.submit(limit("B2", 102, 5, Side::Buy));
+        assert_eq!(outcome.status, OrderStatus::Filled);
+        assert_eq!(outcome.trades[0].price, 100);
+    }
+
+    #[test]
+    fn trade_through_is_allowed_with_protection_off() {
+        let mut engine = MatchingEngine::new();
+        engine.add_book("AAPL".to_string());
+        engine.add_venue_book("AAPL".to_string(), "ALT");
+        engine.submit(limit("S1", 101, 5, Side::Sell));
+        engine.submit_to("ALT", limit("S2", 100, 5, Side::Sell));
+
+        let outcome = engine.submit(limit("B1", 101, 5, Side::Buy));
+        assert_eq!(outcome.trades[0].price, 101);
+    }
+
+    #[test]
+    fn compact_drops_empty_levels_and_repairs_the_id_index() {
+        let mut book = book();
+        let id = book.submit(limit("B1", 100, 5, Side::Buy)).order_id;
+        book.buy_orders.insert(99, VecDeque::new());
+        book.orders_by_id.insert("gone".to_string(), limit("B2", 98, 1, Side::Buy));
+        book.orders_by_id.remove(&id);
+
+        let report = book.compact();
+
+        assert_eq!(report, CompactionReport { empty_levels_removed: 1, stale_ids_removed: 1, ids_repaired: 1 });
+        assert_eq!(book.orders_by_id.keys().collect::<Vec<_>>(), vec![&id]);
+        assert_eq!(book.compact(), CompactionReport::default());
+    }
+
+    #[test]
+    fn duplicate_client_order_id_is_rejected_per_trader() {
+        let mut book = book();
+        let first = book.submit(limit("B1", 99, 5, Side::Buy).with_client_order_id("c1")).order_id;
+
+        let outcome = book.submit(limit("B1", 98, 5, Side::Buy).with_client_order_id("c1"));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::DuplicateClientOrderId));
+        assert_eq!(
+            book.add_for_auction(limit("B1", 98, 5, Side::Buy).with_client_order_id("c1")),
+            Err(OrderError::DuplicateClientOrderId)
+        );
+        assert_eq!(book.submit(limit("B2", 98, 5, Side::Buy).with_client_order_id("c1")).status, OrderStatus::Resting);
+        assert_eq!(book.find_by_client_id("B1", "c1").map(|order| order.id.as_str()), Some(first.as_str()));
+
+        book.cancel_order(&first).unwrap();
+        assert_eq!(book.submit(limit("B1", 98, 5, Side::Buy).with_client_order_id("c1")).status, OrderStatus::Resting);
+
+        let orders = vec![
+            limit("B1", 99, 1, Side::Buy).with_client_order_id("c2"),
+            limit("B1", 98, 1, Side::Buy).with_client_order_id("c2"),
+        ];
+        assert_eq!(
+            OrderBook::from_resting_orders("AAPL".to_string(), orders).err(),
+            Some(OrderError::DuplicateClientOrderId)
+        );
+    }
+
+    #[test]
+    fn reprocessed_pegs_and_stops_keep_their_client_order_id() {
+        let mut book = book();
+        book.submit(limit("B1", 99, 5, Side::Buy));
+        book.submit(limit("S1", 105, 5, Side::Sell));
+        let peg = book.submit(limit("P1", 1, 5, Side::Buy).with_peg(PegType::BestBid, 0).with_client_order_id("peg")).order_id;
+        let stop = Order::stop("T1".to_string(), "AAPL".to_string(), 100, qty(1), Side::Buy).with_client_order_id("stop");
+        book.submit(stop);
+
+        book.submit(limit("B2", 100, 2, Side::Buy));
+        book.submit(limit("S2", 100, 1, Side::Sell));
+
+        assert_eq!(book.find_by_client_id("P1", "peg").map(|order| (order.id.as_str(), order.price)), Some((peg.as_str(), 100)));
+        assert!(book.rejected_orders().is_empty());
+        assert_eq!(book.trades_for("T1").len(), 1);
+    }
+
+    #[test]
+    fn replacing_amend_keeps_the_client_order_id() {
+        let mut book = book();
+        let original = book.submit(limit("B1", 100, 10, Side::Buy).with_client_order_id("c1")).order_id;
+
+        book.amend_order(&original, 101, qty(10)).unwrap();
+
+        let replacement = book.find_by_client_id("B1", "c1").unwrap();
+        assert_ne!(replacement.id, original);
+        assert_eq!(replacement.price, 101);
+    }
+
+    #[test]
+    fn match_step_limit_rests_remainder_partway() {
+        let mut book = book();
+        book.set_max_match_steps(Some(2));
+        for trader_id in ["S1", "S2", "S3", "S4"] {
+            book.submit(limit(trader_id, 100, 1, Side::Sell));
+        }
+
+        let outcome = book.submit(limit("B1", 101, 4, Side::Buy));
+
+        assert_eq!(outcome.trades.len(), 2);
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.resting_quantity, qty(2));
+        assert_eq!(book.ladder(), (vec![(101, qty(2))], vec![(100, qty(2))]));
+    }
+
+    #[test]
+    fn match_step_limit_can_cancel_remainder() {
+        let mut book = book();
+        book.set_max_match_steps(Some(2));
+        book.set_cancel_on_step_limit(true);
+        for trader_id in ["S1", "S2", "S3", "S4"] {
+            book.submit(limit(trader_id, 100, 1, Side::Sell));
+        }
+
+        let outcome = book.submit(limit("B1", 101, 4, Side::Buy));
+
+        assert_eq!(outcome.trades.len(), 2);
+        assert_eq!(outcome.resting_quantity, Quantity::ZERO);
+        assert_eq!(book.ladder(), (vec![], vec![(100, qty(2))]));
+    }
+
+    #[test]
+    fn fill_probability_is_repeatable_per_seed() {
+        let run = |probability: f64, seed: u64| {
+            let mut book = book();
+            book.set_fill_probability(Some(probability), seed);
+            for trader_id in ["S1", "S2", "S3", "S4", "S5", "S6"] {
+                book.submit(limit(trader_id, 100, 1, Side::Sell));
+            }
+            let outcome = book.submit(limit("B1", 100, 6, Side::Buy));
+            outcome.trades.iter().map(|trade| trade.sell_trader_id.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(run(0.5, 7), run(0.5, 7));
+        assert_eq!(run(1.0, 7).len(), 6);
+        assert!(run(0.0, 7).is_empty());
+    }
+
+    #[test]
+    fn debug_dump_lists_levels_from_the_touch() {
+        let mut book = book();
+        book.set_id_generator(SequentialIdGenerator::default());
+        book.submit(limit("B1", 99, 2, Side::Buy));
+        book.submit(limit("S1", 101, 3, Side::Sell).with_hidden());
+        book.submit(limit("S2", 102, 1, Side::Sell));
+
+        let expected = "OrderBook AAPL\n  best bid: 99\n  best ask: 101\n  trades: 0\n  asks:\n    101:\n      ORD-2 S1 x3 hidden\n    102:\n      ORD-3 S2 x1\n  bids:\n    99:\n      ORD-1 B1 x2";
+        assert_eq!(book.debug_dump(), expected);
+    }
+
+    #[test]
+    fn settlement_batches_net_trades_per_trader_pair() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        book.submit(limit("B1", 100, 2, Side::Buy));
+        book.submit(limit("B2", 100, 1, Side::Buy));
+        book.submit(limit("B1", 100, 2, Side::Buy));
+
+        let batches = book.settlement_batches();
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[&("B1".to_string(), "S1".to_string())], (qty(4), 400));
+        assert_eq!(batches[&("B2".to_string(), "S1".to_string())], (qty(1), 100));
+    }
+
+    #[test]
+    fn price_improvement_fills_a_crossing_buy_below_the_ask() {
+        let fill_price = |improvement: bool, bid: Option<u64>| {
+            let mut book = book();
+            book.set_price_improvement(improvement);
+            if let Some(bid) = bid {
+                book.submit(limit("B0", bid, 1, Side::Buy));
+            }
+            book.submit(limit("S1", 100, 5, Side::Sell));
+            book.submit(limit("B1", 104, 5, Side::Buy)).trades[0].price
+        };
+
+        assert_eq!(fill_price(true, Some(96)), 98);
+        assert_eq!(fill_price(false, Some(96)), 100);
+        assert_eq!(fill_price(true, Some(99)), 100);
+        assert_eq!(fill_price(true, None), 100);
+    }
+
+    #[test]
+    fn price_improvement_lifts_a_crossing_sell_above_the_bid() {
+        let mut book = book();
+        book.set_price_improvement(true);
+        book.submit(limit("B1", 100, 5, Side::Buy));
+        book.submit(limit("S0", 104, 1, Side::Sell));
+
+        let outcome = book.submit(limit("S1", 95, 5, Side::Sell));
+
+        assert_eq!(outcome.trades[0].price, 102);
+    }
+
+    #[test]
+    fn oldest_orders_sorts_by_timestamp_then_arrival() {
+        let start = Utc::now() - chrono::Duration::days(1);
+        let now = std::sync::Arc::new(std::sync::Mutex::new(start));
+        let mut book = book();
+        let clock = now.clone();
+        book.set_clock(move || *clock.lock().unwrap());
+
+        let first = book.submit(limit("B1", 99, 1, Side::Buy)).order_id;
+        let second = book.submit(limit("S1", 101, 1, Side::Sell)).order_id;
+        *now.lock().unwrap() += chrono::Duration::seconds(1);
+        book.submit(limit("B2", 98, 1, Side::Buy));
+
+        let oldest: Vec<&str> = book.oldest_orders(2).iter().map(|order| order.id.as_str()).collect();
+        assert_eq!(oldest, vec![first.as_str(), second.as_str()]);
+        assert_eq!(book.oldest_orders(10).len(), 3);
+    }
+
+    #[test]
+    fn reduce_only_order_is_clamped_to_position() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+        book.submit(limit("T1", 100, 5, Side::Buy));
+        assert_eq!(book.position("T1"), Some((Side::Buy, qty(5))));
+
+        let outcome = book.submit(limit("T1", 105, 8, Side::Sell).with_reduce_only());
+
+        assert_eq!(outcome.status, OrderStatus::Resting);
+        assert_eq!(outcome.resting_quantity, qty(5));
+        assert_eq!(book.ladder(), (vec![], vec![(105, qty(5))]));
+
+        let outcome = book.submit(limit("FLAT", 105, 1, Side::Sell).with_reduce_only());
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::NoPositionToReduce));
+    }
+
+    #[test]
+    fn center_of_mass_weights_every_displayed_level() {
+        let mut book = book();
+        book.submit(limit("B1", 100, 1, Side::Buy));
+        book.submit(limit("B2", 96, 3, Side::Buy));
+        book.submit(limit("B3", 90, 5, Side::Buy).with_hidden());
+
+        assert_eq!(book.center_of_mass(Side::Buy), Some(97.0));
+        assert_eq!(book.center_of_mass(Side::Sell), None);
+    }
+
+    #[test]
+    fn improved_buy_jumps_to_front_of_better_level() {
+        let mut book = book();
+        let improver = book.submit(limit("B1", 100, 5, Side::Buy)).order_id;
+        book.submit(limit("B2", 101, 5, Side::Buy));
+        book.submit(limit("B3", 101, 5, Side::Buy));
+
+        book.improve_order(&improver, 101).unwrap();
+
+        assert_eq!(book.ladder().0, vec![(101, qty(15))]);
+        let trades = book.submit(limit("S1", 101, 6, Side::Sell)).trades;
+        let buyers: Vec<_> = trades.iter().map(|trade| trade.buy_trader_id.as_str()).collect();
+        assert_eq!(buyers, vec!["B1", "B2"]);
+    }
+
+    #[test]
+    fn improved_sell_jumps_to_front_of_better_level() {
+        let mut book = book();
+        let improver = book.submit(limit("S1", 102, 5, Side::Sell)).order_id;
+        book.submit(limit("S2", 101, 5, Side::Sell));
+
+        assert_eq!(book.improve_order(&improver, 103), Err(OrderError::NotAnImprovement));
+        book.improve_order(&improver, 101).unwrap();
+
+        let trades = book.submit(limit("B1", 101, 3, Side::Buy)).trades;
+        assert_eq!(trades[0].sell_order_id, improver);
+        assert_eq!(book.ladder().1, vec![(101, qty(7))]);
+    }
+
+    #[test]
+    fn improved_order_keeps_its_place_when_others_join_the_level() {
+        let mut book = book();
+        let improver = book.submit(limit("B1", 100, 5, Side::Buy)).order_id;
+        book.submit(limit("B2", 101, 5, Side::Buy));
+        book.improve_order(&improver, 101).unwrap();
+        book.submit(limit("B3", 101, 5, Side::Buy));
+
+        let trades = book.submit(limit("S1", 101, 15, Side::Sell)).trades;
+        let buyers: Vec<_> = trades.iter().map(|trade| trade.buy_trader_id.as_str()).collect();
+        assert_eq!(buyers, vec!["B1", "B2", "B3"]);
+    }
+
+    #[test]
+    fn improve_order_refuses_to_cross_a_hidden_order() {
+        let mut book = book();
+        let bid = book.submit(limit("B1", 99, 5, Side::Buy)).order_id;
+        book.submit(limit("S1", 101, 5, Side::Sell).with_hidden());
+
+        assert_eq!(book.improve_order(&bid, 101), Err(OrderError::CrossedBook));
+        assert_eq!(book.improve_order(&bid, 100), Ok(()));
+    }
+
+    #[test]
+    fn metrics_summarize_the_displayed_book() {
+        let mut book = book();
+        book.submit(limit("B1", 99, 2, Side::Buy));
+        book.submit(limit("B2", 98, 3, Side::Buy));
+        book.submit(limit("B3", 100, 4, Side::Buy).with_hidden());
+        book.submit(limit("S1", 102, 1, Side::Sell));
+
+        let metrics = book.metrics();
+
+        assert_eq!((metrics.best_bid, metrics.best_ask, metrics.spread), (Some(99), Some(102), Some(3)));
+        assert_eq!((metrics.bid_levels, metrics.bid_quantity), (2, qty(5)));
+        assert_eq!((metrics.ask_levels, metrics.ask_quantity), (1, qty(1)));
+        assert_eq!((metrics.trade_count, metrics.last_price), (0, None));
+    }
+
+    #[test]
+    fn quantity_serializes_through_its_decimal_string() {
+        let quantity: Quantity = "2.5".parse().unwrap();
+        assert_eq!(String::from(quantity), "2.5");
+        assert_eq!(Quantity::try_from("2.5".to_string()), Ok(quantity));
+        assert_eq!(Quantity::try_from("-1".to_string()), Err(QuantityError));
+    }
+
+    #[test]
+    fn pooled_trades_match_unpooled_ones_and_use_the_clock() {
+        let at = Utc::now() - chrono::Duration::days(1);
+        let run = |mut book: OrderBook| {
+            book.set_clock(move || at);
+            let mut fills = Vec::new();
+            for _ in 0..3 {
+                book.submit(limit("S1", 100, 2, Side::Sell));
+                let outcome = book.submit(limit("B1", 100, 2, Side::Buy));
+                fills.extend(outcome.trades.iter().map(|trade| (trade.price, trade.quantity, trade.timestamp)));
+                book.recycle_trades(outcome.trades);
+                let history = book.drain_trades();
+                book.recycle_trades(history);
+            }
+            fills
+        };
+
+        let pooled = run(OrderBook::with_trade_pool("AAPL".to_string(), 4));
+        assert_eq!(pooled, run(book()));
+        assert_eq!(pooled, vec![(100, qty(2), at); 3]);
+    }
+
+    #[test]
+    fn non_crossing_order_rests_untouched() {
+        let mut book = book();
+        book.submit(limit("S1", 101, 5, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 100, 8, Side::Buy));
+
+        assert_eq!(outcome.status, OrderStatus::Resting);
+        assert_eq!(outcome.resting_quantity, qty(8));
+        assert!(outcome.trades.is_empty());
+    }
+
+    #[test]
+    fn partly_matched_order_reports_partially_filled_then_rests() {
+        let mut book = book();
+        book.submit(limit("S1", 100, 5, Side::Sell));
+
+        let outcome = book.submit(limit("B1", 100, 8, Side::Buy));
+
+        assert_eq!(outcome.status, OrderStatus::PartiallyFilled);
+        assert_eq!(outcome.resting_quantity, qty(3));
+        assert_eq!(outcome.trades.len(), 1);
+    }
+
+    #[test]
+    fn market_order_on_empty_book_reports_no_liquidity() {
+        let outcome = book().submit(market_buy(5));
+        assert_eq!(outcome.status, OrderStatus::Rejected(OrderError::NoLiquidity));
+    }
+
+    #[test]
+    fn crossing_quantity_counts_overlap_of_touches() {
+        let mut book_a = book();
+        book_a.submit(limit("A1", 102, 5, Side::Buy));
+        book_a.submit(limit("A2", 101, 5, Side::Buy));
+        book_a.submit(limit("A3", 103, 2, Side::Sell));
+        let mut book_b = book();
+        book_b.submit(limit("B1", 101, 3, Side::Sell));
+        book_b.submit(limit("B2", 102, 10, Side::Sell));
+        book_b.submit(limit("B3", 100, 4, Side::Buy));
+
+        // A's bid at 102 takes 3 at 101 and 2 at 102; B's bid is below A's ask
+        assert_eq!(crossing_quantity(&book_a, &book_b), qty(5));
+        assert_eq!(crossing_quantity(&book_b, &book_a), qty(5));
+    }
+
+    #[test]
+    fn triggered_stop_limit_rests_when_limit_does_not_fill() {
+        let mut book = book();
+        book.submit(limit("S1", 101, 1, Side::Sell));
+        book.submit(limit("S2", 103, 5, Side::Sell));
+        let stop = Order::stop_limit("T1".to_string(), "AAPL".to_string(), 101, 102, qty(4), Side::Buy);
+        assert_eq!(book.submit(stop).status, OrderStatus::Pending);
+
+        book.submit(limit("B1", 101, 1, Side::Buy));
+
+        assert!(book.pending_stops().is_empty());
+        assert_eq!(book.trade_count(), 1);
+        assert_eq!(book.ladder(), (vec![(102, qty(4))], vec![(103, qty(5))]));
+    }
+
+    #[test]
+    fn estimated_time_to_fill_uses_the_traded_rate_on_a_fixed_clock() {
+        let start = Utc::now() - chrono::Duration::days(1);
+        let now = std::sync::Arc::new(std::sync::Mutex::new(start));
+        let mut book = book();
+        let clock = now.clone();
+        book.set_clock(move || *clock.lock().unwrap());
+
+        book.submit(limit("S1", 100, 10, Side::Sell));
+        book.submit(limit("B1", 100, 5, Side::Buy));
+        *now.lock().unwrap() += chrono::Duration::seconds(10);
+        book.submit(limit("B2", 100, 5, Side::Buy));
+
+        book.submit(limit("B3", 99, 4, Side::Buy));
+        let waiting = book.submit(limit("B4", 99, 6, Side::Buy)).order_id;
+
+        // 10 traded over 10 seconds; 4 ahead plus its own 6 takes 10 seconds
+        assert_eq!(book.estimated_time_to_fill(&waiting), Some(Duration::from_secs(10)));
+        assert_eq!(book.estimated_time_to_fill("missing"), None);
+    }
+
+    #[test]
+    fn format_price_with_scale_zero_prints_integer() {
+        let book = book();
+        assert_eq!(book.format_price(10000), "10000");
+    }
+
+    #[test]
+    fn format_price_with_scale_two_places_decimal_point() {
+        let mut book = book();
+        book.set_price_scale(2);
+        assert_eq!(book.format_price(10000), "100.00");
+        assert_eq!(book.format_price(5), "0.05");
+
+        book.submit(limit("B1", 10050, 5, Side::Buy));
+        assert!(book.format_order_book().contains("100.50"));
+    }
+
+    #[test]
+    fn cancel_beyond_pulls_orders_past_the_threshold() {
+        let mut book = book();
+        book.submit(limit("B1", 100, 1, Side::Buy));
+        book.submit(limit("B2", 98, 1, Side::Buy));
+        book.submit(limit("B3", 97, 1, Side::Buy));
+        book.submit(limit("S1", 103, 1, Side::Sell));
+        book.submit(limit("S2", 105, 1, Side::Sell));
+
+        let bids: Vec<_> = book.cancel_beyond(Side::Buy, 99).iter().map(|order| order.price).collect();
+        let asks: Vec<_> = book.cancel_beyond(Side::Sell, 103).iter().map(|order| order.price).collect();
+
+        assert_eq!((bids, asks), (vec![98, 97], vec![105]));
+        assert_eq!(book.ladder(), (vec![(100, qty(1))], vec![(103, qty(1))]));
+        assert!(book.cancel_beyond(Side::Sell, u64::MAX).is_empty());
+    }
+
+    #[test]
+    fn books_with_same_net_resting_state_are_economically_equal() {
+        let mut direct = book();
+        direct.submit(limit("B1", 100, 10, Side::Buy));
+        direct.submit(limit("S1", 105, 5, Side::Sell));
+
+        let mut replayed = book();
+        replayed.submit(limit("B2", 100, 4, Side::Buy));
+        replayed.submit(limit("S2", 105, 8, Side::Sell));
+        replayed.submit(limit("B3", 105, 3, Side::Buy));
+        replayed.submit(limit("B4", 100, 6, Side::Buy));
+        let stray = replayed.submit(limit("B5", 99, 1, Side::Buy)).order_id;
+
+        assert!(!direct.economically_equal(&replayed));
+        replayed.cancel_order(&stray).unwrap();
+        assert!(direct.economically_equal(&replayed));
+        assert!(replayed.economically_equal(&direct));
+
+        let other_symbol = OrderBook::new("MSFT".to_string());
+        assert!(!other_symbol.economically_equal(&book()));
+    }
+}